@@ -0,0 +1,52 @@
+// Compares traversal of the enum node layout against the packed one.
+// Run with `cargo bench --bench traversal [mesh]` from the `render` directory.
+use render::packed::{self, PackedBih};
+use render::scene::{compute_bih, Scene};
+use render::traverse::traverse;
+use std::time::Instant;
+use ultraviolet::Vec3;
+
+const RUNS: u32 = 5;
+
+fn time<F: FnMut() -> usize>(name: &str, mut f: F) {
+    let mut best = u128::MAX;
+    let mut hits = 0;
+    for _ in 0..RUNS {
+        let now = Instant::now();
+        hits = f();
+        best = best.min(now.elapsed().as_micros());
+    }
+    println!("{name:>8}: {best} us (best of {RUNS}), {hits} hits");
+}
+
+fn main() {
+    let mesh = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with('-'))
+        .unwrap_or_else(|| concat!(env!("CARGO_MANIFEST_DIR"), "/../buddha.wobj").to_string());
+
+    let mut scene = Scene::new();
+    scene.add_wavefront(Vec3::zero(), &mesh).unwrap();
+    let bih = compute_bih(&scene, 6);
+    let packed = PackedBih::from(&bih);
+    println!(
+        "nodes: {} ({} bytes enum, {} bytes packed)",
+        bih.nodes.len(),
+        bih.nodes.len() * std::mem::size_of::<render::bih::Node>(),
+        packed.nodes.len() * std::mem::size_of::<packed::PackedNode>()
+    );
+
+    let camera = render::camera::new(8., 6., 5.).set_position(Vec3::new(0.0, 1.0, -6.));
+    let rays: Vec<_> = camera.iter_rays(800, 600).map(|(_, _, ray)| ray).collect();
+
+    time("enum", || {
+        rays.iter()
+            .filter(|ray| traverse(&scene, &bih, 0, ray, 1.0, f32::MAX).is_some())
+            .count()
+    });
+    time("packed", || {
+        rays.iter()
+            .filter(|ray| packed::traverse(&scene, &packed, 0, ray, 1.0, f32::MAX).is_some())
+            .count()
+    });
+}
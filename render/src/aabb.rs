@@ -21,6 +21,11 @@ pub fn extents(aabb: &Aabb) -> Vec3 {
     (aabb.maxs - aabb.mins).map(|x| x.clamp(0.0, std::f32::MAX))
 }
 
+pub fn surface_area(aabb: &Aabb) -> f32 {
+    let e = extents(aabb);
+    2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+}
+
 pub fn join(lhs: &Aabb, rhs: &Aabb) -> Aabb {
     Aabb {
         mins: Vec3::min_by_component(lhs.mins, rhs.mins),
@@ -75,6 +80,12 @@ mod tests {
         assert_eq!(extents(&EMPTY), Vec3::zero());
     }
 
+    #[test]
+    fn test_surface_area() {
+        assert_eq!(surface_area(&EMPTY), 0.0);
+        assert_eq!(surface_area(&TEST), 6.0);
+    }
+
     #[test]
     fn test_join_empty() {
         let right = join(&EMPTY, &TEST);
@@ -0,0 +1,341 @@
+use crate::bih::BihState;
+use crate::camera::Camera;
+use crate::scene::{self, ObjectHandle, Scene};
+use std::path::Path;
+use ultraviolet::bivec::Bivec3;
+use ultraviolet::rotor::Rotor3;
+use ultraviolet::vec::Vec3;
+use ultraviolet::{Lerp, Slerp};
+
+// Keyframe animation of object and camera transforms, and batch rendering of
+// the frames to numbered image files.
+//
+// A track holds keyframes sorted by time. Positions are interpolated
+// linearly and rotations spherically, so circular motions need enough keys
+// (`turntable` uses one per frame). Before the first and after the last key
+// a track holds still.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Keyframe {
+    pub time: f32,
+    pub position: Vec3,
+    pub rotation: Rotor3,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Track {
+    keys: Vec<Keyframe>,
+}
+
+impl Track {
+    pub fn new(mut keys: Vec<Keyframe>) -> Self {
+        assert!(!keys.is_empty(), "track without keyframes");
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Track { keys }
+    }
+
+    pub fn keys(&self) -> &[Keyframe] {
+        &self.keys
+    }
+
+    pub fn start(&self) -> f32 {
+        self.keys[0].time
+    }
+
+    pub fn end(&self) -> f32 {
+        self.keys[self.keys.len() - 1].time
+    }
+
+    // Position and rotation at `time`.
+    pub fn sample(&self, time: f32) -> (Vec3, Rotor3) {
+        let next = self.keys.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return (self.keys[0].position, self.keys[0].rotation);
+        }
+        let a = &self.keys[next - 1];
+        let Some(b) = self.keys.get(next) else {
+            return (a.position, a.rotation);
+        };
+        let t = (time - a.time) / (b.time - a.time);
+        let position = a.position.lerp(b.position, t);
+        let rotation = a.rotation.slerp(b.rotation, t).normalized();
+        (position, rotation)
+    }
+}
+
+// One full turn about the axis through `center`, over `duration`, with
+// `steps` keys after the first. The track moves an object whose transform is
+// the identity at rest, or orbits a camera looking at `center` from its
+// position at time 0 when composed with it (see `Animation::camera`).
+pub fn turntable(center: Vec3, axis: Vec3, duration: f32, steps: u32) -> Track {
+    assert!(steps >= 4, "turntable: at least 4 steps");
+    let plane = Bivec3::from_normalized_axis(axis.normalized());
+    let keys = (0..=steps)
+        .map(|k| {
+            let turn = k as f32 / steps as f32;
+            let rotation = Rotor3::from_angle_plane(turn * std::f32::consts::TAU, plane);
+            Keyframe {
+                time: turn * duration,
+                position: center - rotation * center,
+                rotation,
+            }
+        })
+        .collect();
+    Track::new(keys)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub objects: Vec<(ObjectHandle, Track)>,
+    // Applied after the camera's own transform: a camera at `p` looking
+    // along `r` is placed at `position + rotation * p` looking along
+    // `rotation * r`.
+    pub camera: Option<Track>,
+}
+
+impl Animation {
+    // Time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        let objects = self.objects.iter().map(|(_, track)| track.end());
+        objects
+            .chain(self.camera.iter().map(Track::end))
+            .fold(0.0, f32::max)
+    }
+
+    // Moves the animated objects to their transform at `time`.
+    pub fn apply(&self, scene: &mut Scene, time: f32) {
+        for (handle, track) in self.objects.iter() {
+            let (position, rotation) = track.sample(time);
+            scene.set_transform(*handle, position, rotation);
+        }
+    }
+
+    // Makes the animated objects move, from where `apply` put them at
+    // shutter open, to their transform at `time` at shutter close.
+    pub fn apply_motion(&self, scene: &mut Scene, time: f32) {
+        for (handle, track) in self.objects.iter() {
+            let (position, rotation) = track.sample(time);
+            scene.set_motion(*handle, position, rotation);
+        }
+    }
+
+    pub fn camera(&self, camera: &Camera, time: f32) -> Camera {
+        match &self.camera {
+            None => camera.clone(),
+            Some(track) => {
+                let (position, rotation) = track.sample(time);
+                camera
+                    .set_position(position + rotation * camera.position())
+                    .set_orientation(rotation * camera.orientation())
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BihUpdate {
+    Refit,
+    Rebuild,
+}
+
+pub struct BatchOptions {
+    pub frames: u32,
+    pub fps: f32,
+    pub update: BihUpdate,
+    pub leaf_bound: u32, // when rebuilding
+    pub maxdepth: usize,
+    pub resolution: (u32, u32),
+    pub shutter: f32, // fraction of a frame the shutter is open; 0 disables motion blur
+    pub samples: u32, // rays per pixel over the shutter interval
+}
+
+// `path` with the frame number before the extension: `out/spin.pfm` becomes
+// `out/spin_0007.pfm`.
+pub fn frame_path(path: &str, frame: u32) -> String {
+    let p = Path::new(path);
+    let stem = p.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+    let name = match p.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{stem}_{frame:04}.{ext}"),
+        None => format!("{stem}_{frame:04}"),
+    };
+    p.with_file_name(name).to_string_lossy().into_owned()
+}
+
+// Renders frame `i` at time `i / fps` to `frame_path(output, i)`, updating
+// `bih` after moving the objects. With a shutter, objects are blurred along
+// their motion until `shutter / fps` later; the camera is not. Returns the
+// files written.
+pub fn render_frames(
+    scene: &mut Scene,
+    bih: &mut BihState,
+    camera: &Camera,
+    animation: &Animation,
+    options: &BatchOptions,
+    output: &str,
+) -> std::io::Result<Vec<String>> {
+    let (xres, yres) = options.resolution;
+    let mut written = Vec::new();
+    for frame in 0..options.frames {
+        let time = frame as f32 / options.fps;
+        animation.apply(scene, time);
+        if options.shutter > 0.0 {
+            animation.apply_motion(scene, time + options.shutter / options.fps);
+        }
+        match options.update {
+            BihUpdate::Refit => scene::refit_bih(scene, bih),
+            BihUpdate::Rebuild => *bih = scene::compute_bih(scene, options.leaf_bound),
+        }
+        let camera = animation.camera(camera, time);
+        let fb = if options.shutter > 0.0 {
+            let samples = options.samples;
+            crate::trace::render_motion_blur(
+                options.maxdepth,
+                scene,
+                bih,
+                &camera,
+                xres,
+                yres,
+                samples,
+            )
+        } else {
+            crate::trace::render(options.maxdepth, scene, bih, &camera, xres, yres)
+        };
+        let path = frame_path(output, frame);
+        crate::framebuffer::save(&fb, &path)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx(a: Vec3, b: Vec3) -> bool {
+        (a - b).mag() < 1e-4
+    }
+
+    #[test]
+    fn test_sample() {
+        let quarter = Rotor3::from_rotation_xz(std::f32::consts::FRAC_PI_2);
+        let track = Track::new(vec![
+            Keyframe {
+                time: 2.0,
+                position: Vec3::new(2.0, 0.0, 0.0),
+                rotation: quarter,
+            },
+            Keyframe {
+                time: 0.0,
+                position: Vec3::zero(),
+                rotation: Rotor3::identity(),
+            },
+        ]);
+        assert_eq!((track.start(), track.end()), (0.0, 2.0));
+        assert_eq!(track.sample(-1.0).0, Vec3::zero());
+        assert_eq!(track.sample(3.0).0, Vec3::new(2.0, 0.0, 0.0));
+        let (position, rotation) = track.sample(1.0);
+        assert!(approx(position, Vec3::unit_x()));
+        // halfway through a quarter turn
+        let eighth = Rotor3::from_rotation_xz(std::f32::consts::FRAC_PI_4);
+        assert!(approx(rotation * Vec3::unit_x(), eighth * Vec3::unit_x()));
+    }
+
+    #[test]
+    fn test_turntable() {
+        let center = Vec3::new(1.0, 0.0, 1.0);
+        let track = turntable(center, Vec3::unit_y(), 8.0, 8);
+        for time in [0.0, 1.0, 3.0, 8.0] {
+            let (position, rotation) = track.sample(time);
+            // the center stays put at the keys
+            assert!(approx(position + rotation * center, center));
+        }
+        // half a turn
+        let (position, rotation) = track.sample(4.0);
+        let p = center + Vec3::unit_x();
+        assert!(approx(position + rotation * p, center - Vec3::unit_x()));
+
+        // a camera orbiting the center keeps looking at it
+        let animation = Animation {
+            objects: Vec::new(),
+            camera: Some(track),
+        };
+        let camera = crate::camera::new(1.0, 1.0, 1.0).set_position(Vec3::new(1.0, 0.0, -4.0));
+        let moved = animation.camera(&camera, 2.0);
+        assert!(
+            approx(moved.position(), Vec3::new(-4.0, 0.0, 1.0))
+                || approx(moved.position(), Vec3::new(6.0, 0.0, 1.0))
+        );
+        let forward = moved.orientation() * Vec3::unit_z();
+        assert!(approx(moved.position() + forward * 5.0, center));
+        assert_eq!(animation.duration(), 8.0);
+    }
+
+    #[test]
+    fn test_render_frames() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        let mut scene = Scene::new();
+        scene
+            .materials
+            .push(crate::types::default_material(Vec3::one()));
+        scene.lights.push(crate::types::Light {
+            position: Vec3::new(0.0, 0.0, -10.0),
+            intensity: 5.0,
+            color: Vec3::one(),
+        });
+        let sphere = scene.add_wavefront(Vec3::new(2.0, 0.0, 0.0), path).unwrap();
+        let animation = Animation {
+            objects: vec![(sphere, turntable(Vec3::zero(), Vec3::unit_y(), 4.0, 4))],
+            camera: None,
+        };
+        let camera = crate::camera::new(8.0, 6.0, 5.0).set_position(Vec3::new(0.0, 0.0, -10.0));
+        let dir = std::env::temp_dir().join(format!("bih-rs-test-frames-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("frames.pfm");
+        let output = output.to_str().unwrap();
+        let mut frames = Vec::new();
+        for update in [BihUpdate::Refit, BihUpdate::Rebuild] {
+            let mut bih = scene::compute_bih(&scene, 4);
+            let options = BatchOptions {
+                frames: 3,
+                fps: 1.0,
+                update,
+                leaf_bound: 4,
+                maxdepth: 1,
+                resolution: (16, 12),
+                shutter: 0.0,
+                samples: 1,
+            };
+            let written =
+                render_frames(&mut scene, &mut bih, &camera, &animation, &options, output).unwrap();
+            assert_eq!(written[2], frame_path(output, 2));
+            assert!(written[2].ends_with("frames_0002.pfm"));
+            frames.push(
+                written
+                    .iter()
+                    .map(|p| std::fs::read(p).unwrap())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        // the sphere moves, and both updates render the same frames
+        assert_ne!(frames[0][0], frames[0][1]);
+        assert_eq!(frames[0], frames[1]);
+
+        // a blurred frame differs from the sharp one
+        let mut bih = scene::compute_bih(&scene, 4);
+        let options = BatchOptions {
+            frames: 1,
+            fps: 1.0,
+            update: BihUpdate::Refit,
+            leaf_bound: 4,
+            maxdepth: 1,
+            resolution: (16, 12),
+            shutter: 0.5,
+            samples: 4,
+        };
+        let written =
+            render_frames(&mut scene, &mut bih, &camera, &animation, &options, output).unwrap();
+        assert_ne!(std::fs::read(&written[0]).unwrap(), frames[0][0]);
+        assert!(scene.is_moving(0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use crate::bih::BihState;
+use crate::camera::Camera;
+use crate::framebuffer::{self, Framebuffer};
+use crate::scene::Scene;
+use crate::traverse::traverse;
+use crate::types::Hit;
+
+// Arbitrary output variables: per-pixel data about the primary hit, for
+// debugging and denoising. Pixels where the primary ray escapes have an
+// infinite depth, a zero normal, and -1 for every index.
+pub struct Aovs {
+    pub depth: Framebuffer,       // hit distance `t` (1 channel)
+    pub normal: Framebuffer,      // shading normal from `nbuffer` (3 channels)
+    pub triangle: Framebuffer,    // triangle index (1 channel)
+    pub object: Framebuffer,      // index in `Scene::objects` (1 channel)
+    pub material: Framebuffer,    // material index (1 channel)
+    pub barycentric: Framebuffer, // weights of t0, t1, t2 (3 channels)
+}
+
+pub fn new(xres: u32, yres: u32) -> Aovs {
+    Aovs {
+        depth: framebuffer::new(xres, yres, 1),
+        normal: framebuffer::new(xres, yres, 3),
+        triangle: framebuffer::new(xres, yres, 1),
+        object: framebuffer::new(xres, yres, 1),
+        material: framebuffer::new(xres, yres, 1),
+        barycentric: framebuffer::new(xres, yres, 3),
+    }
+}
+
+impl Aovs {
+    fn set_hit(&mut self, scene: &Scene, x: u32, y: u32, hit: &Hit) {
+        let tri = hit.tri;
+        let object = scene.object_of_triangle(tri).map_or(-1.0, |o| o as f32);
+        let material = scene.tbuffer[tri as usize].mat;
+        self.depth.set_pixel(x, y, &[hit.t]);
+        self.normal.set_rgb(x, y, scene.nbuffer[tri as usize]);
+        self.triangle.set_pixel(x, y, &[tri as f32]);
+        self.object.set_pixel(x, y, &[object]);
+        self.material.set_pixel(x, y, &[material as f32]);
+        self.barycentric
+            .set_pixel(x, y, &[1.0 - hit.u - hit.v, hit.u, hit.v]);
+    }
+
+    fn set_miss(&mut self, x: u32, y: u32) {
+        self.depth.set_pixel(x, y, &[f32::INFINITY]);
+        self.triangle.set_pixel(x, y, &[-1.0]);
+        self.object.set_pixel(x, y, &[-1.0]);
+        self.material.set_pixel(x, y, &[-1.0]);
+    }
+
+    pub fn passes(&self) -> [(&'static str, &Framebuffer); 6] {
+        [
+            ("depth", &self.depth),
+            ("normal", &self.normal),
+            ("triangle", &self.triangle),
+            ("object", &self.object),
+            ("material", &self.material),
+            ("barycentric", &self.barycentric),
+        ]
+    }
+
+    // Writes each pass to `{prefix}_{pass}.{ext}`, `ext` being any extension
+    // understood by `framebuffer::save`.
+    pub fn save(&self, prefix: &str, ext: &str) -> std::io::Result<()> {
+        for (name, fb) in self.passes() {
+            framebuffer::save(fb, &format!("{prefix}_{name}.{ext}"))?;
+        }
+        Ok(())
+    }
+}
+
+// Casts primary rays only and records the first hit for each pixel. Indices
+// are stored as f32 and are therefore exact up to 2^24.
+pub fn render(scene: &Scene, bih: &BihState, camera: &Camera, xres: u32, yres: u32) -> Aovs {
+    let mut aovs = new(xres, yres);
+    camera.iter_rays(xres, yres).for_each(|(x, y, ray)| {
+        match traverse(scene, bih, 0, &ray, 1.0, f32::MAX) {
+            Some(hit) => aovs.set_hit(scene, x, y, &hit),
+            None => aovs.set_miss(x, y),
+        }
+    });
+    aovs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ultraviolet::vec::Vec3;
+    use wfront::loader::{Mesh, Triangle, V3};
+
+    // A large triangle in the plane z = 0, in front of the camera.
+    fn triangle_scene() -> Scene {
+        let mesh = Mesh {
+            vertices: vec![V3(-6.0, -6.0, 0.0), V3(6.0, -6.0, 0.0), V3(0.0, 6.0, 0.0)],
+            normals: Vec::new(),
+            texcoords: Vec::new(),
+            colors: Vec::new(),
+            triangles: vec![Triangle(1, 2, 3)],
+            uv_triangles: Vec::new(),
+            materials: Vec::new(),
+            material_names: Vec::new(),
+            groups: Vec::new(),
+        };
+        let mut scene = Scene::new();
+        scene.add_mesh(Vec3::zero(), mesh);
+        scene
+    }
+
+    #[test]
+    fn test_render() {
+        let scene = triangle_scene();
+        let bih = crate::scene::compute_bih(&scene, 4);
+        let camera = crate::camera::new(8.0, 6.0, 5.0).set_position(Vec3::new(0.0, 0.0, -10.0));
+        let aovs = render(&scene, &bih, &camera, 4, 4);
+
+        // pixel (2, 2) looks along (1, 0.75, 5) and hits (2, 1.5, 0)
+        let depth = aovs.depth.pixel(2, 2)[0];
+        assert!((depth - Vec3::new(2.0, 1.5, 10.0).mag()).abs() < 1e-4);
+        assert_eq!(aovs.triangle.pixel(2, 2), &[0.0]);
+        assert_eq!(aovs.object.pixel(2, 2), &[0.0]);
+        assert_eq!(aovs.material.pixel(2, 2), &[0.0]);
+        assert_eq!(aovs.normal.rgb(2, 2).z.abs(), 1.0);
+        let weights: f32 = aovs.barycentric.pixel(2, 2).iter().sum();
+        assert!((weights - 1.0).abs() < 1e-5);
+
+        // the corner ray passes below the triangle
+        assert_eq!(aovs.depth.pixel(0, 0), &[f32::INFINITY]);
+        assert_eq!(aovs.triangle.pixel(0, 0), &[-1.0]);
+        assert_eq!(aovs.object.pixel(0, 0), &[-1.0]);
+        assert_eq!(aovs.material.pixel(0, 0), &[-1.0]);
+        assert_eq!(aovs.normal.rgb(0, 0), Vec3::zero());
+    }
+}
@@ -34,7 +34,8 @@ pub struct BihState {
     pub index: Vec<ObjIndex>, // The BIH indexes into this array
     pub nodes: Vec<Node>,     // Nodes of the tree
     pub boxes: Vec<Aabb>,
-    global: Aabb,
+    pub(crate) global: Aabb,
+    pub empty_space_cuts: u32, // counted by `compute_bih`
 }
 
 pub fn sort_objects(
@@ -45,7 +46,7 @@ pub fn sort_objects(
     left_obj: u32,
     right_obj: u32,
 ) -> (ObjIndex, f32, f32, f32, f32) {
-    assert!(left_obj < right_obj);
+    assert!(left_obj <= right_obj);
     let mut left_obj = left_obj as i64;
     let mut right_obj = right_obj as i64;
     let mut lclip: f32 = -f32::MAX;
@@ -95,30 +96,41 @@ pub fn index_of_max(vec: &Vec3) -> u8 {
     }
 }
 
+// Leaf holding no primitive. `start > stop` makes the range empty.
+pub const EMPTY_LEAF: Node = Node::Leaf { start: 1, stop: 0 };
+
+// Returns the number of empty-space cuts, which shrink the box of a node
+// instead of creating children.
 pub fn compute_bih(
     leaf_bound: u32,
     bboxes: &[Aabb],
     global: &Aabb,
     index: &mut [u32],
     nodes: &mut Vec<Node>,
-) {
+) -> u32 {
     let mut stack: Vec<StackFrame> = Vec::new();
+    let mut empty_space_cuts = 0;
     let size = bboxes.len();
     assert!(size < u32::MAX as usize);
+    assert_eq!(size, index.len());
+    // Children are appended as they are created, so the array only ever
+    // contains initialised nodes; the capacity is merely a hint.
     *nodes = Vec::with_capacity(2 * size);
-    unsafe { nodes.set_len(size) };
+    nodes.push(EMPTY_LEAF);
+    if size == 0 {
+        return 0;
+    }
     let init_frame = StackFrame {
         start: 0,
-        stop: index.len() as u32 - 1,
+        stop: size as u32 - 1,
         bbox: global.clone(),
         node_index: 0,
     };
-    let mut cursor: u32 = &init_frame.node_index + 1;
     stack.push(init_frame);
 
     'construction: loop {
         match stack.pop() {
-            None => break 'construction,
+            None => break 'construction empty_space_cuts,
             Some(mut frame) => {
                 let start = frame.start;
                 let stop = frame.stop;
@@ -135,12 +147,17 @@ pub fn compute_bih(
                     'retry: loop {
                         let d = dim as usize;
                         let half_dim = (local_bbox.mins[d] + local_bbox.maxs[d]) * 0.5;
+                        // An empty-space cut must strictly shrink the box, which may
+                        // not be the case once its extent reaches float precision.
+                        let shrinks =
+                            local_bbox.mins[d] < half_dim && half_dim < local_bbox.maxs[d];
                         let (left_end, lclip, rclip, lmin, rmax) =
                             sort_objects(bboxes, index, half_dim, d, start, stop);
 
                         if left_end == stop + 1 {
-                            if rmax < half_dim {
+                            if shrinks && rmax < half_dim {
                                 local_bbox.maxs[d] = half_dim;
+                                empty_space_cuts += 1;
                                 stack.push(frame);
                                 continue 'construction;
                             } else {
@@ -153,8 +170,9 @@ pub fn compute_bih(
                                 }
                             }
                         } else if left_end == start {
-                            if half_dim < lmin {
+                            if shrinks && half_dim < lmin {
                                 local_bbox.mins[d] = half_dim;
+                                empty_space_cuts += 1;
                                 stack.push(frame);
                                 continue 'construction;
                             } else {
@@ -169,8 +187,7 @@ pub fn compute_bih(
                         } else {
                             let mut left_bbox = local_bbox.clone();
                             left_bbox.maxs[d] = half_dim;
-                            let left_index = cursor;
-                            cursor += 1;
+                            let left_index = nodes.len() as NodeIndex;
                             let left = StackFrame {
                                 start,
                                 stop: (left_end - 1),
@@ -179,14 +196,16 @@ pub fn compute_bih(
                             };
                             let mut right_bbox = local_bbox.clone();
                             right_bbox.mins[d] = half_dim;
-                            let right_index = cursor;
-                            cursor += 1;
+                            let right_index = left_index + 1;
                             let right = StackFrame {
                                 start: left_end,
                                 stop,
                                 bbox: right_bbox,
                                 node_index: right_index,
                             };
+                            // Placeholders, overwritten when the frames are popped.
+                            nodes.push(EMPTY_LEAF);
+                            nodes.push(EMPTY_LEAF);
                             let node: Node = Node::Node {
                                 axis: dim,
                                 leftclip: lclip,
@@ -222,12 +241,46 @@ pub fn alloc<E: Elt>(state: &E::State, objects: &[E::T], leaf_bound: u32) -> Bih
         global = crate::aabb::join(aabb, &global);
     }
     let mut nodes: Vec<Node> = Vec::new();
-    compute_bih(leaf_bound, &boxes, &global, &mut index, &mut nodes);
+    let empty_space_cuts = compute_bih(leaf_bound, &boxes, &global, &mut index, &mut nodes);
     BihState {
         index,
         nodes,
         boxes,
         global,
+        empty_space_cuts,
+    }
+}
+
+// Recomputes the boxes, clip planes and bounding box of a BIH after its
+// objects moved, keeping the tree and the index.
+pub fn refit<E: Elt>(state: &E::State, objects: &[E::T], bih: &mut BihState) {
+    assert_eq!(objects.len(), bih.boxes.len());
+    for (aabb, obj) in bih.boxes.iter_mut().zip(objects) {
+        *aabb = E::extents(state, obj);
+    }
+    bih.global = refit_node(bih, 0);
+}
+
+// Refits the subtree at `node` and returns its bounding box.
+fn refit_node(bih: &mut BihState, node: NodeIndex) -> Aabb {
+    match bih.nodes[node as usize] {
+        Node::Leaf { start, stop } => (start..=stop).fold(crate::aabb::EMPTY, |acc, i| {
+            crate::aabb::join(&bih.boxes[bih.index[i as usize] as usize], &acc)
+        }),
+        Node::Node { axis, left, .. } => {
+            let left_box = refit_node(bih, left);
+            let right_box = refit_node(bih, left + 1);
+            if let Node::Node {
+                leftclip,
+                rightclip,
+                ..
+            } = &mut bih.nodes[node as usize]
+            {
+                *leftclip = left_box.maxs[axis as usize];
+                *rightclip = right_box.mins[axis as usize];
+            }
+            crate::aabb::join(&left_box, &right_box)
+        }
     }
 }
 
@@ -261,5 +314,451 @@ impl fmt::Display for BihState {
     }
 }
 
+// Costs used for the surface area heuristic estimate in `BihStats`, relative
+// to a single ray/triangle test.
+const SAH_TRAVERSAL_COST: f32 = 1.0;
+const SAH_INTERSECTION_COST: f32 = 1.0;
+
+#[derive(Debug, Default)]
+pub struct BihStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub empty_leaves: usize,
+    pub max_depth: usize,
+    pub avg_depth: f32,           // average over leaves
+    pub leaf_sizes: Vec<usize>,   // leaf_sizes[n] = number of leaves holding n primitives
+    pub empty_space_cuts: usize,  // node boxes shrunk during construction
+    pub avg_clip_overlap: f32,    // average (leftclip - rightclip) / extent, over overlapping nodes
+    pub overlapping_nodes: usize, // inner nodes whose children overlap
+    pub sah_cost: f32,
+    pub memory: usize, // bytes used by nodes, index and boxes
+}
+
+fn leaf_size(start: ObjIndex, stop: ObjIndex) -> usize {
+    if start > stop {
+        0
+    } else {
+        (stop - start) as usize + 1
+    }
+}
+
+impl BihState {
+    pub fn stats(&self) -> BihStats {
+        let mut stats = BihStats {
+            nodes: self.nodes.len(),
+            empty_space_cuts: self.empty_space_cuts as usize,
+            memory: self.nodes.len() * std::mem::size_of::<Node>()
+                + self.index.len() * std::mem::size_of::<ObjIndex>()
+                + self.boxes.len() * std::mem::size_of::<Aabb>(),
+            ..Default::default()
+        };
+        if self.nodes.is_empty() {
+            return stats;
+        }
+
+        let root_area = crate::aabb::surface_area(&self.global);
+        let area_ratio = |bbox: &Aabb| {
+            if root_area > 0.0 {
+                crate::aabb::surface_area(bbox) / root_area
+            } else {
+                1.0
+            }
+        };
+        let mut depth_sum = 0;
+        let mut overlap_sum = 0.0;
+        let mut stack: Vec<(NodeIndex, usize, Aabb)> = vec![(0, 0, self.global.clone())];
+
+        while let Some((node_index, depth, bbox)) = stack.pop() {
+            match &self.nodes[node_index as usize] {
+                Node::Leaf { start, stop } => {
+                    let size = leaf_size(*start, *stop);
+                    stats.leaves += 1;
+                    if size == 0 {
+                        stats.empty_leaves += 1;
+                    }
+                    if stats.leaf_sizes.len() <= size {
+                        stats.leaf_sizes.resize(size + 1, 0);
+                    }
+                    stats.leaf_sizes[size] += 1;
+                    stats.max_depth = stats.max_depth.max(depth);
+                    depth_sum += depth;
+                    stats.sah_cost += SAH_INTERSECTION_COST * size as f32 * area_ratio(&bbox);
+                }
+                Node::Node {
+                    axis,
+                    leftclip,
+                    rightclip,
+                    left,
+                } => {
+                    let d = *axis as usize;
+                    stats.sah_cost += SAH_TRAVERSAL_COST * area_ratio(&bbox);
+                    if leftclip > rightclip {
+                        let extent = bbox.maxs[d] - bbox.mins[d];
+                        stats.overlapping_nodes += 1;
+                        if extent > 0.0 {
+                            overlap_sum += (leftclip - rightclip) / extent;
+                        }
+                    }
+                    let mut left_bbox = bbox.clone();
+                    left_bbox.maxs[d] = f32::min(left_bbox.maxs[d], *leftclip);
+                    let mut right_bbox = bbox;
+                    right_bbox.mins[d] = f32::max(right_bbox.mins[d], *rightclip);
+                    stack.push((*left + 1, depth + 1, right_bbox));
+                    stack.push((*left, depth + 1, left_bbox));
+                }
+            }
+        }
+
+        stats.avg_depth = depth_sum as f32 / stats.leaves as f32;
+        if stats.overlapping_nodes > 0 {
+            stats.avg_clip_overlap = overlap_sum / stats.overlapping_nodes as f32;
+        }
+        stats
+    }
+}
+
+impl fmt::Display for BihStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "nodes:              {}", self.nodes)?;
+        writeln!(f, "leaves:             {}", self.leaves)?;
+        writeln!(f, "empty leaves:       {}", self.empty_leaves)?;
+        writeln!(f, "empty-space cuts:   {}", self.empty_space_cuts)?;
+        writeln!(f, "max depth:          {}", self.max_depth)?;
+        writeln!(f, "average depth:      {:.2}", self.avg_depth)?;
+        writeln!(
+            f,
+            "clip overlap:       {:.4} (average over {} overlapping nodes)",
+            self.avg_clip_overlap, self.overlapping_nodes
+        )?;
+        writeln!(f, "SAH cost:           {:.2}", self.sah_cost)?;
+        writeln!(f, "memory:             {} bytes", self.memory)?;
+        writeln!(f, "leaf sizes:")?;
+        for (size, count) in self.leaf_sizes.iter().enumerate() {
+            if *count > 0 {
+                writeln!(f, "  {size:>4}: {count}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BihError {
+    ChildOutOfRange {
+        node: NodeIndex,
+        left: NodeIndex,
+    },
+    ChildBeforeParent {
+        node: NodeIndex,
+        left: NodeIndex,
+    },
+    NodeSharedOrCyclic {
+        node: NodeIndex,
+    },
+    UnreachableNodes {
+        reachable: usize,
+        allocated: usize,
+    },
+    LeafOutOfRange {
+        node: NodeIndex,
+        start: ObjIndex,
+        stop: ObjIndex,
+    },
+    IndexSlotCoverage {
+        slot: usize,
+        count: usize,
+    },
+    PrimitiveCount {
+        prim: ObjIndex,
+        count: usize,
+    },
+    ClipViolation {
+        node: NodeIndex,
+        prim: ObjIndex,
+        axis: Dim,
+    },
+}
+
+impl fmt::Display for BihError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BihError::ChildOutOfRange { node, left } => {
+                write!(f, "node {node}: children {left}, {} out of range", left + 1)
+            }
+            BihError::ChildBeforeParent { node, left } => {
+                write!(f, "node {node}: left child {left} precedes its parent")
+            }
+            BihError::NodeSharedOrCyclic { node } => {
+                write!(f, "node {node} is reachable through several paths")
+            }
+            BihError::UnreachableNodes {
+                reachable,
+                allocated,
+            } => write!(f, "{reachable} reachable nodes but {allocated} allocated"),
+            BihError::LeafOutOfRange { node, start, stop } => {
+                write!(f, "leaf {node}: range [{start}, {stop}] outside of index")
+            }
+            BihError::IndexSlotCoverage { slot, count } => {
+                write!(f, "index slot {slot} is covered by {count} leaves")
+            }
+            BihError::PrimitiveCount { prim, count } => {
+                write!(f, "primitive {prim} appears {count} times in index")
+            }
+            BihError::ClipViolation { node, prim, axis } => write!(
+                f,
+                "leaf {node}: primitive {prim} exceeds an ancestor clip plane on axis {axis}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BihError {}
+
+impl BihState {
+    // Checks the structural invariants of the tree:
+    // - every allocated node is reachable from the root exactly once, so that
+    //   no slot of `nodes` is left unwritten by the construction;
+    // - children `left` and `left + 1` are in range and come after their parent;
+    // - leaf ranges lie within `index` and cover each slot of `index` once;
+    // - `index` is a permutation of the primitives;
+    // - each primitive's box lies within the clip planes of its ancestors.
+    pub fn validate(&self) -> Result<(), BihError> {
+        let size = self.boxes.len();
+        let mut prim_count = vec![0; size];
+        for &prim in &self.index {
+            if (prim as usize) < size {
+                prim_count[prim as usize] += 1;
+            } else {
+                return Err(BihError::PrimitiveCount { prim, count: 0 });
+            }
+        }
+        if let Some(prim) = prim_count.iter().position(|&c| c != 1) {
+            return Err(BihError::PrimitiveCount {
+                prim: prim as ObjIndex,
+                count: prim_count[prim],
+            });
+        }
+
+        if self.nodes.is_empty() {
+            return Ok(());
+        }
+
+        let mut visited = vec![false; self.nodes.len()];
+        let mut slot_count = vec![0; self.index.len()];
+        // (node, lower bounds, upper bounds) imposed by the ancestors' clip planes
+        let mut stack: Vec<(NodeIndex, Vec3, Vec3)> = vec![(
+            0,
+            Vec3::broadcast(-f32::INFINITY),
+            Vec3::broadcast(f32::INFINITY),
+        )];
+        let mut reachable = 0;
+
+        while let Some((node, lo, hi)) = stack.pop() {
+            if visited[node as usize] {
+                return Err(BihError::NodeSharedOrCyclic { node });
+            }
+            visited[node as usize] = true;
+            reachable += 1;
+
+            match &self.nodes[node as usize] {
+                Node::Leaf { start, stop } => {
+                    let (start, stop) = (*start, *stop);
+                    if start > stop {
+                        continue;
+                    }
+                    if stop as usize >= self.index.len() {
+                        return Err(BihError::LeafOutOfRange { node, start, stop });
+                    }
+                    for slot in start..=stop {
+                        slot_count[slot as usize] += 1;
+                        let prim = self.index[slot as usize];
+                        let bbox = &self.boxes[prim as usize];
+                        for axis in 0..3 {
+                            if bbox.mins[axis] < lo[axis] || bbox.maxs[axis] > hi[axis] {
+                                return Err(BihError::ClipViolation {
+                                    node,
+                                    prim,
+                                    axis: axis as Dim,
+                                });
+                            }
+                        }
+                    }
+                }
+                Node::Node {
+                    axis,
+                    leftclip,
+                    rightclip,
+                    left,
+                } => {
+                    let left = *left;
+                    if left as usize + 1 >= self.nodes.len() {
+                        return Err(BihError::ChildOutOfRange { node, left });
+                    }
+                    if left <= node {
+                        return Err(BihError::ChildBeforeParent { node, left });
+                    }
+                    let d = *axis as usize;
+                    let mut left_hi = hi;
+                    left_hi[d] = f32::min(hi[d], *leftclip);
+                    let mut right_lo = lo;
+                    right_lo[d] = f32::max(lo[d], *rightclip);
+                    stack.push((left + 1, right_lo, hi));
+                    stack.push((left, lo, left_hi));
+                }
+            }
+        }
+
+        if reachable != self.nodes.len() {
+            return Err(BihError::UnreachableNodes {
+                reachable,
+                allocated: self.nodes.len(),
+            });
+        }
+        if let Some(slot) = slot_count.iter().position(|&c| c != 1) {
+            return Err(BihError::IndexSlotCoverage {
+                slot,
+                count: slot_count[slot],
+            });
+        }
+        Ok(())
+    }
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::scene::{compute_bih, Scene};
+
+    const MESHES: [&str; 4] = ["plane.obj", "cube.obj", "sphere2.obj", "buddha.wobj"];
+
+    fn load(mesh: &str) -> Scene {
+        let path = format!("{}/../{mesh}", env!("CARGO_MANIFEST_DIR"));
+        let mut scene = Scene::new();
+        scene.add_wavefront(Vec3::zero(), &path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn test_validate_bundled_meshes() {
+        for mesh in MESHES {
+            let scene = load(mesh);
+            for leaf_bound in [1, 2, 6, 16] {
+                let bih = compute_bih(&scene, leaf_bound);
+                if let Err(e) = bih.validate() {
+                    panic!("{mesh}, leaf_bound={leaf_bound}: {e}")
+                }
+            }
+        }
+    }
+
+    fn from_boxes(boxes: Vec<Aabb>, leaf_bound: u32) -> BihState {
+        let mut index: Vec<u32> = (0..boxes.len() as u32).collect();
+        let global = boxes
+            .iter()
+            .fold(crate::aabb::EMPTY, |acc, b| crate::aabb::join(b, &acc));
+        let mut nodes = Vec::new();
+        let empty_space_cuts =
+            super::compute_bih(leaf_bound, &boxes, &global, &mut index, &mut nodes);
+        BihState {
+            index,
+            nodes,
+            boxes,
+            global,
+            empty_space_cuts,
+        }
+    }
+
+    fn unit_box(at: Vec3) -> Aabb {
+        crate::aabb::make(at, at + Vec3::one())
+    }
+
+    #[test]
+    fn test_degenerate_inputs() {
+        for leaf_bound in [0, 1, 6] {
+            let empty = from_boxes(Vec::new(), leaf_bound);
+            assert!(empty.validate().is_ok());
+            assert_eq!(empty.stats().empty_leaves, 1);
+
+            let single = from_boxes(vec![unit_box(Vec3::zero())], leaf_bound);
+            assert!(single.validate().is_ok());
+            assert_eq!(single.nodes.len(), 1);
+
+            let coincident = from_boxes(vec![unit_box(Vec3::one()); 100], leaf_bound);
+            assert!(coincident.validate().is_ok());
+            assert_eq!(coincident.nodes.len(), 1);
+
+            let point = crate::aabb::make(Vec3::one(), Vec3::one());
+            let points = from_boxes(vec![point; 100], leaf_bound);
+            assert!(points.validate().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_node_count_exceeds_primitive_count() {
+        // With one primitive per leaf, a full tree has 2n - 1 nodes.
+        let boxes = (0..64).map(|i| unit_box(Vec3::new(2.0 * i as f32, 0.0, 0.0)));
+        let bih = from_boxes(boxes.collect(), 1);
+        assert!(bih.validate().is_ok());
+        assert_eq!(bih.nodes.len(), 127);
+    }
+
+    #[test]
+    fn test_empty_space_cuts() {
+        // two clusters far apart along x: the root box shrinks until it
+        // splits them, and then shrinks again around each cluster
+        let near = (0..8).map(|i| unit_box(Vec3::new(0.0, 2.0 * i as f32, 0.0)));
+        let far = (0..8).map(|i| unit_box(Vec3::new(1000.0, 2.0 * i as f32, 0.0)));
+        let bih = from_boxes(near.chain(far).collect(), 2);
+        assert!(bih.validate().is_ok());
+        let stats = bih.stats();
+        assert!(stats.empty_space_cuts > 0);
+        assert_eq!(stats.empty_space_cuts, bih.empty_space_cuts as usize);
+        assert!(format!("{stats}").contains(&format!("{}", stats.empty_space_cuts)));
+
+        // nothing to cut in a row of touching boxes
+        let row = (0..16).map(|i| unit_box(Vec3::new(i as f32, 0.0, 0.0)));
+        assert_eq!(from_boxes(row.collect(), 2).stats().empty_space_cuts, 0);
+    }
+
+    #[test]
+    fn test_validate_detects_corruption() {
+        let scene = load("sphere2.obj");
+        let mut bih = compute_bih(&scene, 6);
+        assert!(bih.validate().is_ok());
+        bih.index[0] = bih.index[1];
+        assert!(matches!(
+            bih.validate(),
+            Err(BihError::PrimitiveCount { .. })
+        ));
+
+        let mut bih = compute_bih(&scene, 6);
+        bih.nodes.push(Node::Leaf { start: 0, stop: 0 });
+        assert!(matches!(
+            bih.validate(),
+            Err(BihError::UnreachableNodes { .. })
+        ));
+
+        let mut bih = compute_bih(&scene, 6);
+        let len = bih.nodes.len() as NodeIndex;
+        if let Node::Node { left, .. } = &mut bih.nodes[0] {
+            *left = len;
+        }
+        assert!(matches!(
+            bih.validate(),
+            Err(BihError::ChildOutOfRange { .. })
+        ));
+    }
+
+    #[test]
+    fn test_validate_detects_clip_violation() {
+        let scene = load("sphere2.obj");
+        let mut bih = compute_bih(&scene, 6);
+        if let Node::Node { leftclip, .. } = &mut bih.nodes[0] {
+            *leftclip = -f32::MAX;
+        }
+        assert!(matches!(
+            bih.validate(),
+            Err(BihError::ClipViolation { .. })
+        ));
+    }
+}
@@ -0,0 +1,469 @@
+use crate::aabb::Aabb;
+use crate::bih::{BihState, Node};
+use crate::scene::{Object, Scene, Triangle};
+use crate::types::Material;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use ultraviolet::rotor::Rotor3;
+use ultraviolet::vec::{Vec2, Vec3};
+
+// On-disk cache of a scene's geometry and of the BIH built over it.
+//
+// Layout (all values little endian):
+//   magic "BIHC" | version: u32 | key: u64
+//   counts: vertices, triangles, objects, nodes, index, boxes, materials
+//           (u32 each)
+//   empty-space cuts: u32
+//   global box: 6 x f32
+//   vbuffer:   3 x f32 per vertex
+//   tbuffer:   t0, t1, t2, mat as u32 per triangle
+//   nbuffer:   3 x f32 per triangle
+//   uvbuffer:  6 x f32 per triangle
+//   objects:   tstart, tstop as u32, pos as 3 x f32, rot as 4 x f32
+//   nodes:     bits as u32, as in `PackedNode`, then the clip planes as
+//              2 x f32 (full precision), or the last primitive as u32 and 0
+//   index:     u32 per primitive
+//   boxes:     6 x f32 per primitive
+//   materials: color as 3 x f32, diffuse, specular, shininess as f32, then
+//              the color, specular and normal maps as u32 (u32::MAX if none)
+//
+// `key` identifies the inputs the cache was built from (see `Hasher`). A
+// cache whose version or key differ is stale and `load` ignores it.
+
+const MAGIC: &[u8; 4] = b"BIHC";
+pub const VERSION: u32 = 4;
+
+// 64-bit FNV-1a. Stable across runs and platforms, unlike
+// `std::collections::hash_map::DefaultHasher`.
+pub struct Hasher(u64);
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Hasher(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub fn write_f32(&mut self, x: f32) {
+        self.write(&x.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, x: u32) {
+        self.write(&x.to_le_bytes());
+    }
+
+    pub fn write_file(&mut self, path: &str) -> std::io::Result<()> {
+        let bytes = std::fs::read(path)?;
+        self.write_u32(bytes.len() as u32);
+        self.write(&bytes);
+        Ok(())
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn truncated() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "bih cache: truncated file")
+}
+
+fn bad_object() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "bih cache: object outside of the triangles",
+    )
+}
+
+struct Writer<'a, W: Write>(&'a mut W);
+
+impl<W: Write> Writer<'_, W> {
+    fn u32(&mut self, x: u32) -> std::io::Result<()> {
+        self.0.write_all(&x.to_le_bytes())
+    }
+
+    fn f32(&mut self, x: f32) -> std::io::Result<()> {
+        self.0.write_all(&x.to_le_bytes())
+    }
+
+    fn vec3(&mut self, v: &Vec3) -> std::io::Result<()> {
+        self.f32(v.x)?;
+        self.f32(v.y)?;
+        self.f32(v.z)
+    }
+
+    fn aabb(&mut self, aabb: &Aabb) -> std::io::Result<()> {
+        self.vec3(&aabb.mins)?;
+        self.vec3(&aabb.maxs)
+    }
+
+    fn map(&mut self, map: Option<u32>) -> std::io::Result<()> {
+        self.u32(map.unwrap_or(u32::MAX))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> std::io::Result<&[u8]> {
+        let end = self.pos.checked_add(n).ok_or_else(truncated)?;
+        let slice = self.bytes.get(self.pos..end).ok_or_else(truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> std::io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> std::io::Result<u64> {
+        let lo = self.u32()? as u64;
+        let hi = self.u32()? as u64;
+        Ok(lo | (hi << 32))
+    }
+
+    fn f32(&mut self) -> std::io::Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    fn vec3(&mut self) -> std::io::Result<Vec3> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn aabb(&mut self) -> std::io::Result<Aabb> {
+        Ok(crate::aabb::make(self.vec3()?, self.vec3()?))
+    }
+
+    fn map(&mut self) -> std::io::Result<Option<u32>> {
+        Ok(Some(self.u32()?).filter(|i| *i != u32::MAX))
+    }
+
+    // Reads `count` items, refusing counts that cannot fit in the file.
+    fn many<T, F>(&mut self, count: u32, item_size: usize, mut f: F) -> std::io::Result<Vec<T>>
+    where
+        F: FnMut(&mut Self) -> std::io::Result<T>,
+    {
+        let count = count as usize;
+        if count.saturating_mul(item_size) > self.bytes.len() - self.pos {
+            return Err(truncated());
+        }
+        (0..count).map(|_| f(self)).collect()
+    }
+}
+
+pub fn write<W: Write>(
+    out: &mut W,
+    key: u64,
+    scene: &Scene,
+    bih: &BihState,
+) -> std::io::Result<()> {
+    out.write_all(MAGIC)?;
+    let mut w = Writer(out);
+    w.u32(VERSION)?;
+    w.u32(key as u32)?;
+    w.u32((key >> 32) as u32)?;
+    for count in [
+        scene.vbuffer.len(),
+        scene.tbuffer.len(),
+        scene.objects.len(),
+        bih.nodes.len(),
+        bih.index.len(),
+        bih.boxes.len(),
+        scene.materials.len(),
+    ] {
+        w.u32(count as u32)?;
+    }
+    w.u32(bih.empty_space_cuts)?;
+    w.aabb(&bih.global)?;
+    for v in &scene.vbuffer {
+        w.vec3(v)?;
+    }
+    for t in &scene.tbuffer {
+        w.u32(t.t0)?;
+        w.u32(t.t1)?;
+        w.u32(t.t2)?;
+        w.u32(t.mat)?;
+    }
+    for n in &scene.nbuffer {
+        w.vec3(n)?;
+    }
+    for uv in scene.uvbuffer.iter().flatten() {
+        w.f32(uv.x)?;
+        w.f32(uv.y)?;
+    }
+    for obj in &scene.objects {
+        w.u32(obj.tstart as u32)?;
+        w.u32(obj.tstop as u32)?;
+        w.vec3(&obj.pos)?;
+        w.f32(obj.rot.s)?;
+        w.f32(obj.rot.bv.xy)?;
+        w.f32(obj.rot.bv.xz)?;
+        w.f32(obj.rot.bv.yz)?;
+    }
+    for node in &bih.nodes {
+        match *node {
+            Node::Leaf { start, stop } => {
+                w.u32((start << 2) | 3)?;
+                w.u32(stop)?;
+                w.u32(0)?;
+            }
+            Node::Node {
+                axis,
+                leftclip,
+                rightclip,
+                left,
+            } => {
+                w.u32((left << 2) | axis as u32)?;
+                w.f32(leftclip)?;
+                w.f32(rightclip)?;
+            }
+        }
+    }
+    for i in &bih.index {
+        w.u32(*i)?;
+    }
+    for aabb in &bih.boxes {
+        w.aabb(aabb)?;
+    }
+    for m in &scene.materials {
+        w.vec3(&m.m_color)?;
+        w.f32(m.m_diffuse)?;
+        w.f32(m.m_specular)?;
+        w.f32(m.m_shininess)?;
+        w.map(m.color_map)?;
+        w.map(m.specular_map)?;
+        w.map(m.normal_map)?;
+    }
+    Ok(())
+}
+
+// Decodes a cache. Returns `Ok(None)` if it was written by another version
+// or for another key, or if the decoded tree fails validation.
+pub fn decode(bytes: &[u8], key: u64) -> std::io::Result<Option<(Scene, BihState)>> {
+    let mut r = Reader { bytes, pos: 0 };
+    if r.take(4)? != MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "bih cache: bad magic",
+        ));
+    }
+    if r.u32()? != VERSION || r.u64()? != key {
+        return Ok(None);
+    }
+    let vcount = r.u32()?;
+    let tcount = r.u32()?;
+    let ocount = r.u32()?;
+    let ncount = r.u32()?;
+    let icount = r.u32()?;
+    let bcount = r.u32()?;
+    let mcount = r.u32()?;
+    let empty_space_cuts = r.u32()?;
+    let global = r.aabb()?;
+
+    let vbuffer = r.many(vcount, 12, |r| r.vec3())?;
+    let tbuffer = r.many(tcount, 16, |r| {
+        Ok(Triangle {
+            t0: r.u32()?,
+            t1: r.u32()?,
+            t2: r.u32()?,
+            mat: r.u32()?,
+        })
+    })?;
+    let nbuffer = r.many(tcount, 12, |r| r.vec3())?;
+    let uvbuffer = r.many(tcount, 24, |r| {
+        let mut corners = [Vec2::zero(); 3];
+        for uv in corners.iter_mut() {
+            *uv = Vec2::new(r.f32()?, r.f32()?);
+        }
+        Ok(corners)
+    })?;
+    let objects = r.many(ocount, 36, |r| {
+        let tstart = r.u32()? as usize;
+        let tstop = r.u32()? as usize;
+        let pos = r.vec3()?;
+        let s = r.f32()?;
+        let bv = ultraviolet::Bivec3::new(r.f32()?, r.f32()?, r.f32()?);
+        Ok(Object {
+            rot: Rotor3::new(s, bv),
+            pos,
+            tstart,
+            tstop,
+            motion: None,
+        })
+    })?;
+    let nodes = r.many(ncount, 12, |r| {
+        let bits = r.u32()?;
+        Ok(match bits & 3 {
+            3 => {
+                let stop = r.u32()?;
+                r.u32()?;
+                Node::Leaf {
+                    start: bits >> 2,
+                    stop,
+                }
+            }
+            axis => Node::Node {
+                axis: axis as u8,
+                leftclip: r.f32()?,
+                rightclip: r.f32()?,
+                left: bits >> 2,
+            },
+        })
+    })?;
+    let index = r.many(icount, 4, |r| r.u32())?;
+    let boxes = r.many(bcount, 24, |r| r.aabb())?;
+    let materials = r.many(mcount, 36, |r| {
+        Ok(Material {
+            m_color: r.vec3()?,
+            m_diffuse: r.f32()?,
+            m_specular: r.f32()?,
+            m_shininess: r.f32()?,
+            color_map: r.map()?,
+            specular_map: r.map()?,
+            normal_map: r.map()?,
+        })
+    })?;
+
+    // objects cover increasing, disjoint runs of triangles
+    let mut next = 0;
+    for obj in objects.iter() {
+        if obj.tstart < next || obj.tstop < obj.tstart || obj.tstop >= tbuffer.len() {
+            return Err(bad_object());
+        }
+        next = obj.tstop + 1;
+    }
+    let in_range = tbuffer
+        .iter()
+        .all(|t| t.t0 < vcount && t.t1 < vcount && t.t2 < vcount);
+    if !in_range || boxes.len() != tbuffer.len() {
+        return Ok(None);
+    }
+    let bih = BihState {
+        index,
+        nodes,
+        boxes,
+        global,
+        empty_space_cuts,
+    };
+    if bih.validate().is_err() {
+        return Ok(None);
+    }
+    let mut scene = Scene::from_geometry(vbuffer, tbuffer, nbuffer, uvbuffer, objects);
+    scene.materials = materials;
+    Ok(Some((scene, bih)))
+}
+
+pub fn save(path: &str, key: u64, scene: &Scene, bih: &BihState) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out, key, scene, bih)?;
+    out.flush()
+}
+
+// Memory-maps the cache at `path` and decodes it. A missing file counts as
+// a stale cache.
+pub fn load(path: &str, key: u64) -> std::io::Result<Option<(Scene, BihState)>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // Safety: the mapping is only read while `map` is alive; concurrent
+    // truncation of the file by another process is not supported.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    decode(&map, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::compute_bih;
+
+    fn sphere() -> Scene {
+        let mut scene = Scene::new();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        scene.add_wavefront(Vec3::new(1.0, 2.0, 3.0), path).unwrap();
+        scene
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mut scene = sphere();
+        scene.materials.push(crate::types::Material {
+            color_map: Some(2),
+            ..crate::types::default_material(Vec3::new(0.1, 0.2, 0.3))
+        });
+        scene.uvbuffer[1] = [Vec2::zero(), Vec2::unit_x(), Vec2::new(0.5, 1.0)];
+        let bih = compute_bih(&scene, 4);
+        let mut bytes = Vec::new();
+        write(&mut bytes, 42, &scene, &bih).unwrap();
+
+        let (loaded, loaded_bih) = decode(&bytes, 42).unwrap().unwrap();
+        assert_eq!(loaded.vbuffer, scene.vbuffer);
+        assert_eq!(loaded.nbuffer, scene.nbuffer);
+        assert_eq!(loaded.uvbuffer, scene.uvbuffer);
+        assert_eq!(loaded.tbuffer.len(), scene.tbuffer.len());
+        assert_eq!(loaded.objects.len(), 1);
+        assert_eq!(loaded.global, scene.global);
+        assert_eq!(loaded.materials.len(), 1);
+        assert_eq!(loaded.materials[0].m_color, Vec3::new(0.1, 0.2, 0.3));
+        assert_eq!(loaded.materials[0].color_map, Some(2));
+        assert_eq!(loaded.materials[0].normal_map, None);
+        assert_eq!(loaded_bih.index, bih.index);
+        assert_eq!(loaded_bih.boxes, bih.boxes);
+        assert_eq!(loaded_bih.empty_space_cuts, bih.empty_space_cuts);
+        assert_eq!(format!("{loaded_bih}"), format!("{bih}"));
+    }
+
+    #[test]
+    fn test_stale_and_truncated() {
+        let scene = sphere();
+        let bih = compute_bih(&scene, 4);
+        let mut bytes = Vec::new();
+        write(&mut bytes, 42, &scene, &bih).unwrap();
+        assert!(decode(&bytes, 43).unwrap().is_none());
+        assert!(decode(&bytes[..bytes.len() - 1], 42).is_err());
+        assert!(decode(b"nope", 42).is_err());
+
+        // an object past the last triangle
+        let objects = 72 + scene.vbuffer.len() * 12 + scene.tbuffer.len() * 52;
+        let tstop = (scene.tbuffer.len() as u32).to_le_bytes();
+        bytes[objects + 4..objects + 8].copy_from_slice(&tstop);
+        let err = decode(&bytes, 42).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let scene = sphere();
+        let bih = compute_bih(&scene, 4);
+        let dir = std::env::temp_dir().join(format!("bih-rs-test-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("scene.bihc");
+        let path = path.to_str().unwrap();
+        assert!(load(path, 42).unwrap().is_none());
+        save(path, 42, &scene, &bih).unwrap();
+        let (loaded, loaded_bih) = load(path, 42).unwrap().unwrap();
+        assert_eq!(loaded.vbuffer, scene.vbuffer);
+        assert_eq!(loaded_bih.index, bih.index);
+        assert!(load(path, 43).unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hasher() {
+        let mut h = Hasher::default();
+        h.write(b"a");
+        assert_eq!(h.finish(), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -61,6 +61,7 @@ impl Iterator for RayIterator<'_> {
                     origin: self.cam.pos,
                     normal,
                     inormal,
+                    time: 0.0,
                 },
             ))
         }
@@ -80,12 +81,26 @@ impl Camera {
         }
     }
 
+    pub fn position(&self) -> Vec3 {
+        self.pos
+    }
+
+    pub fn orientation(&self) -> Rotor3 {
+        self.rot
+    }
+
     pub fn set_position(&self, position: Vec3) -> Self {
         let mut c = self.clone();
         c.pos = position;
         c
     }
 
+    pub fn set_orientation(&self, rot: Rotor3) -> Self {
+        let mut c = self.clone();
+        c.rot = rot;
+        c
+    }
+
     pub fn set_orientation_angle_axis(&self, angle: f32, axis: Vec3) -> Self {
         let mut c = self.clone();
         c.rot = Rotor3::from_angle_plane(angle, Bivec3::from_normalized_axis(axis));
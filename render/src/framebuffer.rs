@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use ultraviolet::vec::Vec3;
+
+// Linear, unclamped floating-point image. Pixels are stored row-major from
+// the top-left corner, with `channels` interleaved f32 samples per pixel.
+#[derive(Clone, Debug)]
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub data: Vec<f32>,
+}
+
+pub fn new(width: u32, height: u32, channels: usize) -> Framebuffer {
+    assert!(channels > 0);
+    Framebuffer {
+        width,
+        height,
+        channels,
+        data: vec![0.0; width as usize * height as usize * channels],
+    }
+}
+
+impl Framebuffer {
+    fn offset(&self, x: u32, y: u32) -> usize {
+        assert!(x < self.width && y < self.height);
+        (y as usize * self.width as usize + x as usize) * self.channels
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> &[f32] {
+        let offset = self.offset(x, y);
+        &self.data[offset..offset + self.channels]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, value: &[f32]) {
+        assert_eq!(value.len(), self.channels);
+        let offset = self.offset(x, y);
+        self.data[offset..offset + self.channels].copy_from_slice(value);
+    }
+
+    pub fn set_rgb(&mut self, x: u32, y: u32, color: Vec3) {
+        self.set_pixel(x, y, &[color.x, color.y, color.z]);
+    }
+
+    pub fn rgb(&self, x: u32, y: u32) -> Vec3 {
+        match self.pixel(x, y) {
+            [v] => Vec3::broadcast(*v),
+            [r, g, b, ..] => Vec3::new(*r, *g, *b),
+            _ => panic!("rgb: framebuffer has {} channels", self.channels),
+        }
+    }
+}
+
+fn unsupported(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, what.to_string())
+}
+
+// Portable float map. Only 1 (`Pf`) and 3 (`PF`) channel images are
+// representable. Scanlines are written bottom-to-top, little endian.
+pub fn write_pfm<W: Write>(fb: &Framebuffer, out: &mut W) -> std::io::Result<()> {
+    let magic = match fb.channels {
+        1 => "Pf",
+        3 => "PF",
+        _ => return Err(unsupported("pfm: only 1 or 3 channels are supported")),
+    };
+    write!(out, "{magic}\n{} {}\n-1.0\n", fb.width, fb.height)?;
+    let row = fb.width as usize * fb.channels;
+    for y in (0..fb.height as usize).rev() {
+        for v in &fb.data[y * row..(y + 1) * row] {
+            out.write_all(&v.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// Baseline uncompressed TIFF with IEEE float samples (SampleFormat = 3),
+// stored as a single strip.
+pub fn write_tiff<W: Write>(fb: &Framebuffer, out: &mut W) -> std::io::Result<()> {
+    let photometric: u16 = match fb.channels {
+        1 => 1,     // BlackIsZero
+        3 | 4 => 2, // RGB
+        _ => return Err(unsupported("tiff: only 1, 3 or 4 channels are supported")),
+    };
+    let channels = fb.channels as u32;
+    let pixel_bytes = fb.data.len() as u32 * 4;
+
+    // Layout: header (8) | pixel data | BitsPerSample array | SampleFormat array | IFD
+    let data_offset: u32 = 8;
+    let bits_offset = data_offset + pixel_bytes;
+    let format_offset = bits_offset + 2 * channels;
+    let ifd_offset = format_offset + 2 * channels;
+
+    out.write_all(b"II*\0")?;
+    out.write_all(&ifd_offset.to_le_bytes())?;
+    for v in &fb.data {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    for _ in 0..channels {
+        out.write_all(&32u16.to_le_bytes())?;
+    }
+    for _ in 0..channels {
+        out.write_all(&3u16.to_le_bytes())?;
+    }
+
+    // Arrays of at most two shorts fit inline in the entry value field.
+    let inline_or = |offset: u32, value: u16| {
+        if channels <= 2 {
+            value as u32
+        } else {
+            offset
+        }
+    };
+    // (tag, type, count, value); type 3 = SHORT, 4 = LONG
+    let mut entries: Vec<(u16, u16, u32, u32)> = vec![
+        (256, 4, 1, fb.width),
+        (257, 4, 1, fb.height),
+        (258, 3, channels, inline_or(bits_offset, 32)),
+        (259, 3, 1, 1),
+        (262, 3, 1, photometric as u32),
+        (273, 4, 1, data_offset),
+        (277, 3, 1, channels),
+        (278, 4, 1, fb.height),
+        (279, 4, 1, pixel_bytes),
+        (284, 3, 1, 1),
+        (339, 3, channels, inline_or(format_offset, 3)),
+    ];
+    if fb.channels == 4 {
+        // ExtraSamples: unassociated alpha
+        entries.push((338, 3, 1, 2));
+        entries.sort_by_key(|e| e.0);
+    }
+
+    out.write_all(&(entries.len() as u16).to_le_bytes())?;
+    for (tag, typ, count, value) in entries {
+        out.write_all(&tag.to_le_bytes())?;
+        out.write_all(&typ.to_le_bytes())?;
+        out.write_all(&count.to_le_bytes())?;
+        out.write_all(&value.to_le_bytes())?;
+    }
+    out.write_all(&0u32.to_le_bytes())?;
+    Ok(())
+}
+
+// Writes `fb` to `path`, picking the format from the file extension.
+pub fn save(fb: &Framebuffer, path: &str) -> std::io::Result<()> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let mut out = BufWriter::new(File::create(path)?);
+    match ext.as_deref() {
+        Some("pfm") => write_pfm(fb, &mut out)?,
+        Some("tif") | Some("tiff") => write_tiff(fb, &mut out)?,
+        _ => {
+            return Err(unsupported(
+                "unknown image extension (expected .pfm or .tiff)",
+            ))
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> Framebuffer {
+        let mut fb = new(2, 2, 3);
+        fb.set_rgb(0, 0, Vec3::new(0.0, 1.0, 2.0));
+        fb.set_rgb(1, 1, Vec3::new(10.0, 20.0, 30.0));
+        fb
+    }
+
+    #[test]
+    fn test_pfm_layout() {
+        let mut out = Vec::new();
+        write_pfm(&gradient(), &mut out).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&out[..header.len()], header);
+        let floats: Vec<f32> = out[header.len()..]
+            .chunks(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(floats.len(), 12);
+        // bottom row first: pixel (1, 1) is the last of the first row written
+        assert_eq!(&floats[3..6], &[10.0, 20.0, 30.0]);
+        assert_eq!(&floats[6..9], &[0.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_pfm_rejects_two_channels() {
+        let mut out = Vec::new();
+        assert!(write_pfm(&new(1, 1, 2), &mut out).is_err());
+    }
+
+    #[test]
+    fn test_tiff_header() {
+        let mut out = Vec::new();
+        let fb = gradient();
+        write_tiff(&fb, &mut out).unwrap();
+        assert_eq!(&out[..4], b"II*\0");
+        let ifd = u32::from_le_bytes([out[4], out[5], out[6], out[7]]) as usize;
+        let count = u16::from_le_bytes([out[ifd], out[ifd + 1]]) as usize;
+        assert_eq!(out.len(), ifd + 2 + count * 12 + 4);
+        assert_eq!(
+            f32::from_le_bytes([out[12], out[13], out[14], out[15]]),
+            1.0
+        );
+        assert_eq!(
+            f32::from_le_bytes([out[44], out[45], out[46], out[47]]),
+            10.0
+        );
+    }
+}
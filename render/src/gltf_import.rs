@@ -0,0 +1,343 @@
+use crate::camera::{self, Camera};
+use crate::scene::{ObjectHandle, Scene};
+use crate::types::{Light, Material};
+use std::path::Path;
+use ultraviolet::mat::{Mat3, Mat4};
+use ultraviolet::vec::Vec3;
+use wfront::loader::{Mesh, Triangle, V3};
+
+// glTF 2.0 import (`.gltf` and `.glb`).
+//
+// The nodes of the default scene are flattened: every mesh primitive becomes
+// an object whose vertices are in world space. Materials are mapped onto the
+// Phong-like `Material`: the base color becomes `m_color`, the metallic
+// factor `m_specular` and the roughness the shininess. Point and
+// spot lights become point lights (spot cones are ignored); directional
+// lights are placed far away against their direction, outside of the scene.
+// Perspective cameras are returned in node order, orthographic ones are
+// skipped. Textures are not loaded.
+
+// Distance of directional lights, in scene radii.
+const DIRECTIONAL_DISTANCE: f32 = 100.0;
+
+// Used for cameras without an aspect ratio; that of the default resolution.
+const DEFAULT_ASPECT_RATIO: f32 = 4.0 / 3.0;
+
+pub struct Import {
+    pub objects: Vec<ObjectHandle>,
+    pub cameras: Vec<Camera>,
+}
+
+fn gltf_error(e: gltf::Error) -> std::io::Error {
+    match e {
+        gltf::Error::Io(e) => e,
+        e => std::io::Error::new(std::io::ErrorKind::InvalidData, format!("gltf: {e}")),
+    }
+}
+
+pub fn material(m: &gltf::Material) -> Material {
+    let pbr = m.pbr_metallic_roughness();
+    let [r, g, b, _alpha] = pbr.base_color_factor();
+    // Blinn-Phong exponent matching a GGX roughness, after Walter et al.
+    let alpha = pbr.roughness_factor().clamp(0.0, 1.0).powi(2).max(1e-3);
+    Material {
+        m_color: Vec3::new(r, g, b),
+        m_diffuse: 1.0,
+        m_specular: 0.04 + 0.96 * pbr.metallic_factor().clamp(0.0, 1.0),
+        m_shininess: 2.0 / (alpha * alpha) - 2.0,
+        color_map: None,
+        specular_map: None,
+        normal_map: None,
+    }
+}
+
+fn translation(m: &Mat4) -> Vec3 {
+    m.cols[3].xyz()
+}
+
+// Camera looking down the node's -z axis, with +y up. `camera` looks down
+// +z with +y pointing down the image.
+fn perspective(world: &Mat4, p: &gltf::camera::Perspective) -> Camera {
+    let right = world.cols[0].xyz().normalized();
+    let up = world.cols[1].xyz().normalized();
+    let back = world.cols[2].xyz().normalized();
+    let rot = Mat3::new(right, -up, -back).into_rotor3();
+    let height = 2.0 * (p.yfov() / 2.0).tan();
+    let width = height * p.aspect_ratio().unwrap_or(DEFAULT_ASPECT_RATIO);
+    camera::new(width, height, 1.0)
+        .set_position(translation(world))
+        .set_orientation(rot)
+}
+
+struct Importer<'a> {
+    buffers: &'a [gltf::buffer::Data],
+    // index in `scene.materials` of the first glTF material
+    material_base: u32,
+    default_material: Option<u32>,
+    directional: Vec<(Vec3, f32, Vec3)>, // direction, intensity, color
+    import: Import,
+}
+
+impl Importer<'_> {
+    fn material_id(&mut self, scene: &mut Scene, m: &gltf::Material) -> u32 {
+        match m.index() {
+            Some(i) => self.material_base + i as u32,
+            None => *self.default_material.get_or_insert_with(|| {
+                scene.materials.push(material(m));
+                (scene.materials.len() - 1) as u32
+            }),
+        }
+    }
+
+    fn primitive(
+        &mut self,
+        scene: &mut Scene,
+        world: &Mat4,
+        primitive: &gltf::Primitive,
+    ) -> std::io::Result<()> {
+        use gltf::mesh::Mode;
+        let reader = primitive.reader(|b| self.buffers.get(b.index()).map(|d| &d.0[..]));
+        let vertices: Vec<V3> = match reader.read_positions() {
+            Some(positions) => positions
+                .map(|p| {
+                    let p = world.transform_point3(Vec3::from(p));
+                    V3(p.x, p.y, p.z)
+                })
+                .collect(),
+            None => return Ok(()),
+        };
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+        if indices.iter().any(|i| *i as usize >= vertices.len()) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "gltf: vertex index out of range",
+            ));
+        }
+        // Mesh triangles are 1-based
+        let tri = |a: u32, b: u32, c: u32| Triangle(a + 1, b + 1, c + 1);
+        let triangles: Vec<Triangle> = match primitive.mode() {
+            Mode::Triangles => indices
+                .chunks_exact(3)
+                .map(|t| tri(t[0], t[1], t[2]))
+                .collect(),
+            Mode::TriangleStrip => indices
+                .windows(3)
+                .enumerate()
+                .map(|(i, t)| match i % 2 {
+                    0 => tri(t[0], t[1], t[2]),
+                    _ => tri(t[1], t[0], t[2]),
+                })
+                .collect(),
+            Mode::TriangleFan if indices.len() >= 3 => indices[1..]
+                .windows(2)
+                .map(|t| tri(indices[0], t[0], t[1]))
+                .collect(),
+            _ => return Ok(()), // points, lines and degenerate fans
+        };
+        if triangles.is_empty() {
+            return Ok(());
+        }
+
+        // glTF texcoords have v pointing down
+        let texcoords = match reader.read_tex_coords(0) {
+            Some(uvs) => uvs.into_f32().map(|[u, v]| V3(u, 1.0 - v, 0.0)).collect(),
+            None => Vec::new(),
+        };
+
+        let mat = self.material_id(scene, &primitive.material());
+        let mesh = Mesh {
+            vertices,
+            normals: Vec::new(),
+            texcoords,
+            colors: Vec::new(),
+            materials: Vec::new(),
+            triangles,
+            uv_triangles: Vec::new(),
+            material_names: Vec::new(),
+            groups: Vec::new(),
+        };
+        let obj = scene.add_mesh(Vec3::zero(), mesh);
+        scene.set_material(obj, mat);
+        self.import.objects.push(obj);
+        Ok(())
+    }
+
+    fn node(&mut self, scene: &mut Scene, parent: &Mat4, node: &gltf::Node) -> std::io::Result<()> {
+        let world = *parent * Mat4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                self.primitive(scene, &world, &primitive)?;
+            }
+        }
+        if let Some(cam) = node.camera() {
+            if let gltf::camera::Projection::Perspective(p) = cam.projection() {
+                self.import.cameras.push(perspective(&world, &p));
+            }
+        }
+        if let Some(light) = node.light() {
+            use gltf::khr_lights_punctual::Kind;
+            let color = Vec3::from(light.color());
+            match light.kind() {
+                Kind::Directional => {
+                    let direction = -world.cols[2].xyz().normalized();
+                    self.directional.push((direction, light.intensity(), color));
+                }
+                Kind::Point | Kind::Spot { .. } => scene.lights.push(Light {
+                    position: translation(&world),
+                    intensity: light.intensity(),
+                    color,
+                }),
+            }
+        }
+        for child in node.children() {
+            self.node(scene, &world, &child)?;
+        }
+        Ok(())
+    }
+}
+
+fn import_document(
+    scene: &mut Scene,
+    document: &gltf::Document,
+    buffers: &[gltf::buffer::Data],
+) -> std::io::Result<Import> {
+    let mut importer = Importer {
+        buffers,
+        material_base: scene.materials.len() as u32,
+        default_material: None,
+        directional: Vec::new(),
+        import: Import {
+            objects: Vec::new(),
+            cameras: Vec::new(),
+        },
+    };
+    scene
+        .materials
+        .extend(document.materials().map(|m| material(&m)));
+
+    let root = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+    for node in root.iter().flat_map(|s| s.nodes()) {
+        importer.node(scene, &Mat4::identity(), &node)?;
+    }
+
+    // The tracer's falloff is linear in the distance: scale the intensity so
+    // that it is unchanged at the center of the scene.
+    let center = (scene.global.mins + scene.global.maxs) * 0.5;
+    let radius = ((scene.global.maxs - scene.global.mins).mag() * 0.5).max(1.0);
+    let distance = DIRECTIONAL_DISTANCE * radius;
+    for (direction, intensity, color) in importer.directional {
+        scene.lights.push(Light {
+            position: center - direction * distance,
+            intensity: intensity * distance,
+            color,
+        });
+    }
+    Ok(importer.import)
+}
+
+// Imports the default scene of a `.gltf` or `.glb` file into `scene`.
+pub fn import(scene: &mut Scene, path: &str) -> std::io::Result<Import> {
+    let gltf::Gltf { document, blob } = gltf::Gltf::open(path).map_err(gltf_error)?;
+    let base = Path::new(path).parent();
+    let buffers = gltf::import_buffers(&document, base, blob).map_err(gltf_error)?;
+    import_document(scene, &document, &buffers)
+}
+
+// Same as `import` for a file in memory. External buffers are resolved
+// relative to `base`.
+pub fn import_slice(
+    scene: &mut Scene,
+    bytes: &[u8],
+    base: Option<&Path>,
+) -> std::io::Result<Import> {
+    let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(bytes).map_err(gltf_error)?;
+    let buffers = gltf::import_buffers(&document, base, blob).map_err(gltf_error)?;
+    import_document(scene, &document, &buffers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/corpus/triangle.gltf");
+
+    fn approx(a: Vec3, b: Vec3) -> bool {
+        (a - b).mag() < 1e-5
+    }
+
+    #[test]
+    fn test_import() {
+        let mut scene = Scene::new();
+        scene
+            .materials
+            .push(crate::types::default_material(Vec3::one()));
+        let import = import(&mut scene, PATH).unwrap();
+
+        // the root's translation applies after the child's scale
+        assert_eq!(import.objects.len(), 1);
+        assert_eq!(
+            scene.vbuffer,
+            vec![
+                Vec3::new(1.0, 0.0, 0.0),
+                Vec3::new(3.0, 0.0, 0.0),
+                Vec3::new(1.0, 2.0, 0.0)
+            ]
+        );
+        assert_eq!(scene.tbuffer[0].mat, 1);
+        assert_eq!(scene.materials[1].m_color, Vec3::new(1.0, 0.0, 0.0));
+        assert!((scene.materials[1].m_specular - 0.52).abs() < 1e-6);
+
+        assert_eq!(scene.lights.len(), 2);
+        assert_eq!(scene.lights[0].position, Vec3::new(0.0, 3.0, 0.0));
+        assert_eq!(scene.lights[0].color, Vec3::new(1.0, 0.5, 0.25));
+        // the sun shines downwards, so it is above the scene
+        let sun = &scene.lights[1];
+        assert!(sun.position.y > 100.0 && (sun.position.x - 2.0).abs() < 1e-3);
+
+        // the camera at z = 5 looks down -z with a 1.5 x 1 screen
+        assert_eq!(import.cameras.len(), 1);
+        let rays: Vec<_> = import.cameras[0].iter_rays(2, 2).collect();
+        let (_, _, top_left) = rays[0];
+        let (_, _, bottom_right) = rays[3];
+        assert_eq!(top_left.origin, Vec3::new(0.0, 0.0, 5.0));
+        assert!(approx(
+            top_left.normal,
+            Vec3::new(-0.375, 0.25, -1.0).normalized()
+        ));
+        assert!(approx(
+            bottom_right.normal,
+            Vec3::new(0.375, -0.25, -1.0).normalized()
+        ));
+    }
+
+    // Wraps the JSON of `PATH` in a binary container.
+    #[test]
+    fn test_glb() {
+        let json = std::fs::read(PATH).unwrap();
+        let mut glb = Vec::new();
+        let padded = json.len().div_ceil(4) * 4;
+        let total = 12 + 8 + padded;
+        for x in [0x46546c67u32, 2, total as u32, padded as u32, 0x4e4f534a] {
+            glb.extend_from_slice(&x.to_le_bytes());
+        }
+        glb.extend_from_slice(&json);
+        glb.resize(total, b' ');
+
+        let mut scene = Scene::new();
+        let import = import_slice(&mut scene, &glb, None).unwrap();
+        assert_eq!(import.objects.len(), 1);
+        assert_eq!(scene.tbuffer.len(), 1);
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let mut scene = Scene::new();
+        let err = import(&mut scene, "does-not-exist.gltf").err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::scene::{ObjectHandle, Scene};
+use std::collections::HashMap;
+use ultraviolet::mat::Mat4;
+use ultraviolet::rotor::Rotor3;
+use ultraviolet::vec::Vec3;
+use wfront::loader::{Mesh, Triangle, V3};
+
+// Scene graph: a forest of named nodes, each with a local transform relative
+// to its parent, optionally instancing one of the graph's meshes. Several
+// nodes may instance the same mesh. `build` flattens the graph into the
+// world-space buffers of a `Scene`, one object per instancing node.
+
+pub type NodeId = usize;
+pub type MeshId = usize;
+
+// Scale, then rotate, then translate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Rotor3,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn identity() -> Self {
+        Transform {
+            translation: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: Vec3::one(),
+        }
+    }
+
+    pub fn from_translation(translation: Vec3) -> Self {
+        Transform {
+            translation,
+            ..Transform::identity()
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.translation)
+            * self.rotation.into_matrix().into_homogeneous()
+            * Mat4::from_nonuniform_scale(self.scale)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+pub struct Node {
+    pub name: String,
+    pub transform: Transform,
+    pub mesh: Option<MeshId>,
+    pub material: Option<u32>, // overrides the mesh's materials
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    meshes: Vec<Mesh>,
+    names: HashMap<String, NodeId>,
+}
+
+impl SceneGraph {
+    pub fn new() -> Self {
+        SceneGraph::default()
+    }
+
+    pub fn add_mesh(&mut self, mesh: Mesh) -> MeshId {
+        self.meshes.push(mesh);
+        self.meshes.len() - 1
+    }
+
+    pub fn mesh(&self, id: MeshId) -> &Mesh {
+        &self.meshes[id]
+    }
+
+    // Adds a node under `parent`, or a root node. Names need not be unique;
+    // `find` returns the first node added with a given name.
+    pub fn add_node(
+        &mut self,
+        parent: Option<NodeId>,
+        name: &str,
+        transform: Transform,
+        mesh: Option<MeshId>,
+    ) -> NodeId {
+        let id = self.nodes.len();
+        if let Some(p) = parent {
+            self.nodes[p].children.push(id);
+        }
+        self.nodes.push(Node {
+            name: name.to_string(),
+            transform,
+            mesh,
+            material: None,
+            parent,
+            children: Vec::new(),
+        });
+        self.names.entry(name.to_string()).or_insert(id);
+        id
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId> {
+        self.names.get(name).copied()
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id]
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Moves `id` under `parent` (or to the roots), keeping its local
+    // transform. Returns false, leaving the graph unchanged, if that would
+    // create a cycle.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == id {
+                return false;
+            }
+            ancestor = self.nodes[a].parent;
+        }
+        if let Some(old) = self.nodes[id].parent {
+            self.nodes[old].children.retain(|c| *c != id);
+        }
+        if let Some(p) = parent {
+            self.nodes[p].children.push(id);
+        }
+        self.nodes[id].parent = parent;
+        true
+    }
+
+    // Transform from the node's space to world space.
+    pub fn world_matrix(&self, id: NodeId) -> Mat4 {
+        let node = &self.nodes[id];
+        let local = node.transform.matrix();
+        match node.parent {
+            Some(p) => self.world_matrix(p) * local,
+            None => local,
+        }
+    }
+
+    // World matrix of every node, parents before children.
+    fn world_matrices(&self) -> Vec<Mat4> {
+        let mut world = vec![Mat4::identity(); self.nodes.len()];
+        let mut stack: Vec<NodeId> = (0..self.nodes.len())
+            .filter(|i| self.nodes[*i].parent.is_none())
+            .collect();
+        while let Some(id) = stack.pop() {
+            let node = &self.nodes[id];
+            let local = node.transform.matrix();
+            world[id] = match node.parent {
+                Some(p) => world[p] * local,
+                None => local,
+            };
+            stack.extend_from_slice(&node.children);
+        }
+        world
+    }
+
+    // Adds the world-space geometry of every node with a mesh to `scene`,
+    // in node order. Returns the objects along with their nodes.
+    pub fn build(&self, scene: &mut Scene) -> Vec<(NodeId, ObjectHandle)> {
+        let world = self.world_matrices();
+        let mut objects = Vec::new();
+        for (id, node) in self.nodes.iter().enumerate() {
+            if let Some(mesh) = node.mesh {
+                let mut mesh = transform_mesh(&world[id], &self.meshes[mesh]);
+                if node.material.is_some() {
+                    mesh.materials.clear();
+                    mesh.material_names.clear();
+                }
+                let obj = scene.add_mesh(Vec3::zero(), mesh);
+                if let Some(material) = node.material {
+                    scene.set_material(obj, material);
+                }
+                objects.push((id, obj));
+            }
+        }
+        objects
+    }
+}
+
+// Applies `m` to the vertices of `mesh`. Mirroring transforms flip the
+// triangles, so that the normals computed by the scene still face out.
+pub fn transform_mesh(m: &Mat4, mesh: &Mesh) -> Mesh {
+    let mut mesh = mesh.clone();
+    for v in mesh.vertices.iter_mut() {
+        let V3(x, y, z) = *v;
+        let p = m.transform_point3(Vec3::new(x, y, z));
+        *v = V3(p.x, p.y, p.z);
+    }
+    if m.determinant() < 0.0 {
+        for Triangle(_, t1, t2) in mesh.triangles.iter_mut() {
+            std::mem::swap(t1, t2);
+        }
+    }
+    // normals would need the inverse transpose; the scene ignores them
+    mesh.normals.clear();
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ultraviolet::bivec::Bivec3;
+
+    fn triangle() -> Mesh {
+        Mesh {
+            vertices: vec![V3(0.0, 0.0, 0.0), V3(1.0, 0.0, 0.0), V3(0.0, 1.0, 0.0)],
+            normals: Vec::new(),
+            texcoords: Vec::new(),
+            colors: Vec::new(),
+            triangles: vec![Triangle(1, 2, 3)],
+            uv_triangles: Vec::new(),
+            materials: Vec::new(),
+            material_names: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    fn approx(a: Vec3, b: Vec3) -> bool {
+        (a - b).mag() < 1e-5
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let mut graph = SceneGraph::new();
+        let mesh = graph.add_mesh(triangle());
+        let quarter = Rotor3::from_angle_plane(
+            std::f32::consts::FRAC_PI_2,
+            Bivec3::from_normalized_axis(Vec3::unit_z()),
+        );
+        let root = graph.add_node(
+            None,
+            "root",
+            Transform {
+                translation: Vec3::new(10.0, 0.0, 0.0),
+                rotation: quarter,
+                scale: Vec3::one(),
+            },
+            None,
+        );
+        let child = graph.add_node(
+            Some(root),
+            "child",
+            Transform {
+                translation: Vec3::new(1.0, 0.0, 0.0),
+                scale: Vec3::new(2.0, 3.0, 1.0),
+                ..Transform::identity()
+            },
+            Some(mesh),
+        );
+        // a mirrored instance of the same mesh
+        let mirror = graph.add_node(
+            None,
+            "mirror",
+            Transform {
+                scale: Vec3::new(-1.0, 1.0, 1.0),
+                ..Transform::identity()
+            },
+            Some(mesh),
+        );
+        assert_eq!(graph.find("child"), Some(child));
+        assert_eq!(graph.find("nope"), None);
+        assert_eq!(graph.node(root).children(), &[child]);
+        graph.node_mut(mirror).material = Some(2);
+
+        let mut scene = Scene::new();
+        let objects = graph.build(&mut scene);
+        assert_eq!(objects.len(), 2);
+        assert_eq!((objects[0].0, objects[1].0), (child, mirror));
+
+        // (1, 0, 0) is scaled to (2, 0, 0), moved to (3, 0, 0), rotated to
+        // (0, 3, 0) and moved to (10, 3, 0); (0, 1, 0) ends at (7, 1, 0)
+        let v = |i: usize| scene.vbuffer[i];
+        assert!(approx(v(0), Vec3::new(10.0, 1.0, 0.0)));
+        assert!(approx(v(1), Vec3::new(10.0, 3.0, 0.0)));
+        assert!(approx(v(2), Vec3::new(7.0, 1.0, 0.0)));
+        assert!(approx(
+            graph.world_matrix(child).transform_point3(Vec3::unit_x()),
+            v(1)
+        ));
+
+        assert_eq!((scene.tbuffer[0].mat, scene.tbuffer[1].mat), (0, 2));
+
+        // both instances face +z
+        assert!(approx(scene.nbuffer[0], Vec3::unit_z()));
+        assert!(approx(scene.nbuffer[1], Vec3::unit_z()));
+    }
+
+    #[test]
+    fn test_reparent() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add_node(None, "a", Transform::from_translation(Vec3::unit_x()), None);
+        let b = graph.add_node(
+            Some(a),
+            "b",
+            Transform::from_translation(Vec3::unit_y()),
+            None,
+        );
+        assert!(!graph.set_parent(a, Some(b)));
+        assert!(approx(
+            graph.world_matrix(b).transform_point3(Vec3::zero()),
+            Vec3::new(1.0, 1.0, 0.0)
+        ));
+        assert!(graph.set_parent(b, None));
+        assert!(graph.node(a).children().is_empty());
+        assert!(approx(
+            graph.world_matrix(b).transform_point3(Vec3::zero()),
+            Vec3::unit_y()
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+use crate::bih::BihState;
+use crate::camera::Camera;
+use crate::framebuffer::{self, Framebuffer};
+use crate::scene::Scene;
+use crate::traverse::traverse_stats;
+use ultraviolet::vec::Vec3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    Nodes,
+    Triangles,
+}
+
+impl Metric {
+    fn channel(self) -> usize {
+        match self {
+            Metric::Nodes => 0,
+            Metric::Triangles => 1,
+        }
+    }
+}
+
+// Traversal cost of the primary ray of each pixel: channel 0 holds the
+// number of BIH nodes visited, channel 1 the number of triangle tests.
+pub fn render(scene: &Scene, bih: &BihState, camera: &Camera, xres: u32, yres: u32) -> Framebuffer {
+    let mut fb = framebuffer::new(xres, yres, 2);
+    camera.iter_rays(xres, yres).for_each(|(x, y, ray)| {
+        let (_hit, stats) = traverse_stats(scene, bih, &ray, 1.0, f32::MAX);
+        fb.set_pixel(x, y, &[stats.nodes as f32, stats.triangles as f32]);
+    });
+    fb
+}
+
+const RAMP: [Vec3; 5] = [
+    Vec3::new(0.0, 0.0, 0.0),
+    Vec3::new(0.0, 0.0, 1.0),
+    Vec3::new(0.0, 1.0, 0.0),
+    Vec3::new(1.0, 1.0, 0.0),
+    Vec3::new(1.0, 0.0, 0.0),
+];
+
+// Maps `x` in [0, 1] to black -> blue -> green -> yellow -> red.
+pub fn colormap(x: f32) -> Vec3 {
+    let x = x.clamp(0.0, 1.0) * (RAMP.len() - 1) as f32;
+    let i = (x as usize).min(RAMP.len() - 2);
+    let frac = x - i as f32;
+    RAMP[i] * (1.0 - frac) + RAMP[i + 1] * frac
+}
+
+// Color-maps one metric of a heatmap produced by `render`, normalised by
+// its maximum over the image.
+pub fn colorize(counts: &Framebuffer, metric: Metric) -> Framebuffer {
+    assert_eq!(counts.channels, 2);
+    let c = metric.channel();
+    let max = counts
+        .data
+        .chunks(2)
+        .fold(0.0f32, |acc, px| f32::max(acc, px[c]));
+    let scale = if max > 0.0 { 1.0 / max } else { 0.0 };
+    let mut fb = framebuffer::new(counts.width, counts.height, 3);
+    for y in 0..counts.height {
+        for x in 0..counts.width {
+            fb.set_rgb(x, y, colormap(counts.pixel(x, y)[c] * scale));
+        }
+    }
+    fb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colorize() {
+        assert_eq!(colormap(0.0), Vec3::zero());
+        assert_eq!(colormap(0.5), Vec3::unit_y());
+        assert_eq!(colormap(1.0), Vec3::unit_x());
+        assert_eq!(colormap(7.0), Vec3::unit_x());
+
+        let mut counts = framebuffer::new(3, 1, 2);
+        counts.set_pixel(1, 0, &[2.0, 8.0]);
+        counts.set_pixel(2, 0, &[4.0, 1.0]);
+        // normalised by the maximum of the metric
+        let nodes = colorize(&counts, Metric::Nodes);
+        assert_eq!(nodes.rgb(0, 0), Vec3::zero());
+        assert_eq!(nodes.rgb(1, 0), Vec3::unit_y());
+        assert_eq!(nodes.rgb(2, 0), Vec3::unit_x());
+        let triangles = colorize(&counts, Metric::Triangles);
+        assert_eq!(triangles.rgb(1, 0), Vec3::unit_x());
+        // an image without any work stays black
+        let black = colorize(&framebuffer::new(3, 1, 2), Metric::Nodes);
+        assert_eq!(black.rgb(2, 0), Vec3::zero());
+    }
+}
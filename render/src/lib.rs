@@ -1,8 +1,19 @@
 pub mod aabb;
+pub mod animation;
+pub mod aov;
 pub mod bih;
+pub mod cache;
 pub mod camera;
+pub mod framebuffer;
+pub mod gltf_import;
+pub mod graph;
+pub mod heatmap;
 pub mod moller_trumbore;
+pub mod packed;
+pub mod procedural;
 pub mod scene;
+pub mod scene_file;
+pub mod texture;
 pub mod trace;
 pub mod traverse;
 pub mod triaccel;
@@ -90,6 +90,7 @@ pub fn test_intersection_branchless8(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::types::{new_hit, new_ray};
 
     const P0: Vec3 = Vec3::new(-1.0, 0.0, 1.0);
     const P1: Vec3 = Vec3::new(1.0, 0.0, 1.0);
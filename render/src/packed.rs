@@ -0,0 +1,330 @@
+use crate::bih::{BihState, Node, NodeIndex, ObjIndex};
+use crate::scene::Scene;
+use crate::traverse::intersect_ray;
+use crate::types::{Hit, Ray};
+
+// Compact BIH node, after the layout of the original BIH paper. The two low
+// bits of `bits` hold the split axis, or `LEAF` for leaves; the remaining 30
+// bits hold the index of the left child (the right one being `left + 1`),
+// or the first primitive of a leaf. Inner nodes keep their clip planes in
+// `clip` as bfloat16 (the top half of an f32), rounded outwards so that the
+// children only grow: up for the left clip plane, down for the right one.
+// Leaves store their last primitive in `clip`. This is 8 bytes, against 16
+// for `bih::Node`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(C)]
+pub struct PackedNode {
+    pub bits: u32,
+    pub clip: u32, // left clip plane in the high half, right one in the low half
+}
+
+const LEAF: u32 = 3;
+const MAX_INDEX: u32 = u32::MAX >> 2;
+
+pub enum Unpacked {
+    Leaf {
+        start: ObjIndex,
+        stop: ObjIndex,
+    },
+    Node {
+        axis: usize,
+        leftclip: f32,
+        rightclip: f32,
+        left: NodeIndex,
+    },
+}
+
+// Rounds `x` up, or down, to a bfloat16.
+fn to_bf16(x: f32, up: bool) -> u16 {
+    let bits = x.to_bits();
+    let truncated = (bits >> 16) as u16;
+    // truncating rounds towards zero; step away from zero when that went the
+    // wrong way (a step from the largest finite value gives an infinity)
+    let negative = bits >> 31 == 1;
+    if bits & 0xffff == 0 || x.is_nan() || up == negative {
+        truncated
+    } else {
+        truncated + 1
+    }
+}
+
+#[inline(always)]
+fn from_bf16(x: u16) -> f32 {
+    f32::from_bits((x as u32) << 16)
+}
+
+impl PackedNode {
+    #[inline(always)]
+    pub fn is_leaf(&self) -> bool {
+        self.bits & 3 == LEAF
+    }
+
+    #[inline(always)]
+    pub fn unpack(&self) -> Unpacked {
+        let axis = self.bits & 3;
+        if axis == LEAF {
+            Unpacked::Leaf {
+                start: self.bits >> 2,
+                stop: self.clip,
+            }
+        } else {
+            Unpacked::Node {
+                axis: axis as usize,
+                leftclip: from_bf16((self.clip >> 16) as u16),
+                rightclip: from_bf16(self.clip as u16),
+                left: self.bits >> 2,
+            }
+        }
+    }
+}
+
+impl From<&Node> for PackedNode {
+    fn from(node: &Node) -> Self {
+        match node {
+            Node::Leaf { start, stop } => {
+                assert!(*start <= MAX_INDEX, "pack: primitive index too large");
+                PackedNode {
+                    bits: (start << 2) | LEAF,
+                    clip: *stop,
+                }
+            }
+            Node::Node {
+                axis,
+                leftclip,
+                rightclip,
+                left,
+            } => {
+                assert!(*left <= MAX_INDEX, "pack: node index too large");
+                assert!(*axis < 3);
+                PackedNode {
+                    bits: (left << 2) | *axis as u32,
+                    clip: (to_bf16(*leftclip, true) as u32) << 16
+                        | to_bf16(*rightclip, false) as u32,
+                }
+            }
+        }
+    }
+}
+
+impl From<&PackedNode> for Node {
+    fn from(node: &PackedNode) -> Self {
+        match node.unpack() {
+            Unpacked::Leaf { start, stop } => Node::Leaf { start, stop },
+            Unpacked::Node {
+                axis,
+                leftclip,
+                rightclip,
+                left,
+            } => Node::Node {
+                axis: axis as u8,
+                leftclip,
+                rightclip,
+                left,
+            },
+        }
+    }
+}
+
+pub fn pack(nodes: &[Node]) -> Vec<PackedNode> {
+    nodes.iter().map(PackedNode::from).collect()
+}
+
+pub struct PackedBih {
+    pub nodes: Vec<PackedNode>,
+    pub index: Vec<ObjIndex>,
+}
+
+impl From<&BihState> for PackedBih {
+    fn from(bih: &BihState) -> Self {
+        PackedBih {
+            nodes: pack(&bih.nodes),
+            index: bih.index.clone(),
+        }
+    }
+}
+
+fn nearest(lhs: Option<Hit>, rhs: Option<Hit>) -> Option<Hit> {
+    match (lhs, rhs) {
+        (None, x) | (x, None) => x,
+        (Some(x), Some(y)) => {
+            if x.t < y.t {
+                Some(x)
+            } else {
+                Some(y)
+            }
+        }
+    }
+}
+
+// Same algorithm as `traverse::traverse`, on the packed layout. Children are
+// visited near-to-far along the ray direction. The rounded clip planes make
+// it visit more nodes, but not find other hits.
+pub fn traverse(
+    scene: &Scene,
+    bih: &PackedBih,
+    node_index: u32,
+    ray: &Ray,
+    tmin: f32,
+    tmax: f32,
+) -> Option<Hit> {
+    if tmin >= tmax {
+        return None;
+    };
+    match bih.nodes[node_index as usize].unpack() {
+        Unpacked::Leaf { start, stop } => {
+            intersect_ray(scene, &bih.index, ray, tmin, tmax, &start, &stop)
+        }
+        Unpacked::Node {
+            axis,
+            leftclip,
+            rightclip,
+            left,
+        } => {
+            let origin = ray.origin[axis];
+            let ray_start = origin + ray.normal[axis] * tmin;
+            let ray_stop = origin + ray.normal[axis] * tmax;
+
+            // near child, far child, plane where the ray leaves the near child,
+            // plane where it enters the far child
+            // whether the near child lies wholly before the far one; when the
+            // children touch, only going left-to-right, as in `traverse`
+            let (near, far, near_plane, far_plane, enters_near, enters_far, disjoint) =
+                if ray.normal[axis] >= 0.0 {
+                    let enters_left = ray_start <= leftclip;
+                    let enters_right = rightclip <= ray_stop;
+                    (
+                        left,
+                        left + 1,
+                        leftclip,
+                        rightclip,
+                        enters_left,
+                        enters_right,
+                        leftclip <= rightclip,
+                    )
+                } else {
+                    let enters_right = rightclip <= ray_start;
+                    let enters_left = ray_stop <= leftclip;
+                    (
+                        left + 1,
+                        left,
+                        rightclip,
+                        leftclip,
+                        enters_right,
+                        enters_left,
+                        leftclip < rightclip,
+                    )
+                };
+            let near_exit = f32::min((near_plane - origin) * ray.inormal[axis], tmax);
+            let far_entry = f32::max((far_plane - origin) * ray.inormal[axis], tmin);
+
+            if enters_near {
+                let near_hit = traverse(scene, bih, near, ray, tmin, near_exit);
+                if !enters_far {
+                    near_hit
+                } else if disjoint {
+                    // a hit in the near child is the nearest
+                    match near_hit {
+                        None => traverse(scene, bih, far, ray, far_entry, tmax),
+                        hit => hit,
+                    }
+                } else {
+                    nearest(near_hit, traverse(scene, bih, far, ray, far_entry, tmax))
+                }
+            } else if enters_far {
+                traverse(scene, bih, far, ray, far_entry, tmax)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene::compute_bih;
+    use crate::types::new_ray;
+    use ultraviolet::Vec3;
+
+    #[test]
+    fn test_node_size() {
+        assert_eq!(std::mem::size_of::<PackedNode>(), 8);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let leaf = PackedNode::from(&Node::Leaf {
+            start: 12,
+            stop: 17,
+        });
+        assert!(leaf.is_leaf());
+        assert!(matches!(
+            leaf.unpack(),
+            Unpacked::Leaf {
+                start: 12,
+                stop: 17
+            }
+        ));
+        let node = PackedNode::from(&Node::Node {
+            axis: 2,
+            leftclip: 1.5,
+            rightclip: -0.5,
+            left: 42,
+        });
+        assert!(!node.is_leaf());
+        match node.unpack() {
+            Unpacked::Node {
+                axis,
+                leftclip,
+                rightclip,
+                left,
+            } => assert_eq!((axis, leftclip, rightclip, left), (2, 1.5, -0.5, 42)),
+            Unpacked::Leaf { .. } => panic!("expected an inner node"),
+        }
+
+        // inexact clip planes are rounded outwards
+        let node = PackedNode::from(&Node::Node {
+            axis: 0,
+            leftclip: 0.1,
+            rightclip: -1000.3,
+            left: 1,
+        });
+        let Unpacked::Node {
+            leftclip,
+            rightclip,
+            ..
+        } = node.unpack()
+        else {
+            panic!("expected an inner node");
+        };
+        assert!(0.1 < leftclip && leftclip < 0.101);
+        assert!(-1004.0 <= rightclip && rightclip < -1000.3);
+        assert_eq!(to_bf16(f32::MAX, true), 0x7f80);
+        assert_eq!(
+            from_bf16(to_bf16(f32::NEG_INFINITY, false)),
+            f32::NEG_INFINITY
+        );
+    }
+
+    #[test]
+    fn test_same_hits_as_enum_layout() {
+        let mut scene = Scene::new();
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        scene
+            .add_wavefront(Vec3::new(0.3, -0.2, 0.0), path)
+            .unwrap();
+        let bih = compute_bih(&scene, 2);
+        let packed = PackedBih::from(&bih);
+        let camera = crate::camera::new(8., 6., 5.).set_position(Vec3::new(0.0, 0.0, -10.));
+        for (_x, _y, ray) in camera.iter_rays(80, 60) {
+            // the camera rays, and the same rays coming back from the far
+            // side, so that both directions are taken on every axis
+            let back = new_ray(ray.origin + ray.normal * 20.0, -ray.normal);
+            for ray in [ray, back] {
+                let expected = crate::traverse::traverse(&scene, &bih, 0, &ray, 1.0, f32::MAX);
+                let hit = traverse(&scene, &packed, 0, &ray, 1.0, f32::MAX);
+                assert_eq!(expected.map(|h| (h.t, h.tri)), hit.map(|h| (h.t, h.tri)));
+            }
+        }
+    }
+}
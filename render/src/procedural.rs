@@ -0,0 +1,273 @@
+use crate::texture::Lookup;
+use ultraviolet::vec::Vec3;
+use ultraviolet::Lerp;
+
+// Procedural textures: patterns computed at the hit instead of read from an
+// image. A pattern maps a point to a value in [0, 1], which blends the two
+// colors of the texture.
+//
+// Patterns are solid: they are evaluated at a 3D point, taken from the
+// texcoords (as `(u, v, 0)`), the world-space hit position or the hit
+// position on the object at rest (so that the pattern sticks to an object
+// when it moves), multiplied by `scale`.
+
+// Where the pattern is evaluated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Space {
+    #[default]
+    Uv,
+    World,
+    Object,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pattern {
+    // Unit cubes of alternating colors.
+    Checker,
+    // The edges of the unit cubes, `width` wide, in the second color. On
+    // faces of the cubes (including texcoord space) they draw a grid of
+    // lines, elsewhere a grid of dots.
+    Grid { width: f32 },
+    // Fractal Perlin noise.
+    Noise { octaves: u32 },
+    // Veins across the x axis, displaced by turbulence.
+    Marble { octaves: u32, turbulence: f32 },
+    // Rings around the y axis, displaced by turbulence.
+    Wood { octaves: u32, turbulence: f32 },
+}
+
+// Points within this distance below a cell boundary go to the next cell, so
+// that surfaces lying on a boundary, up to rounding, get a single color.
+const SNAP: f32 = 1e-3;
+
+impl Pattern {
+    pub fn value(&self, p: Vec3) -> f32 {
+        match *self {
+            Pattern::Checker => {
+                let cell = |x: f32| (x + SNAP).floor() as i64;
+                ((cell(p.x) + cell(p.y) + cell(p.z)) & 1) as f32
+            }
+            Pattern::Grid { width } => {
+                let on_edge = |x: f32| (x - x.round()).abs() < width / 2.0;
+                let count = [p.x, p.y, p.z].into_iter().filter(|x| on_edge(*x)).count();
+                if count >= 2 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Pattern::Noise { octaves } => (0.5 + 0.5 * fbm(p, octaves)).clamp(0.0, 1.0),
+            Pattern::Marble {
+                octaves,
+                turbulence: amount,
+            } => {
+                let x = p.x + amount * turbulence(p, octaves);
+                0.5 + 0.5 * (x * std::f32::consts::PI).sin()
+            }
+            Pattern::Wood {
+                octaves,
+                turbulence: amount,
+            } => {
+                let r = (p.x * p.x + p.z * p.z).sqrt() + amount * turbulence(p, octaves);
+                r.rem_euclid(1.0)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Procedural {
+    pub pattern: Pattern,
+    pub space: Space,
+    pub scale: f32, // pattern units per scene (or texcoord) unit
+    pub colors: [Vec3; 2],
+}
+
+impl Procedural {
+    // Black and white, at scale 1.
+    pub fn new(pattern: Pattern, space: Space) -> Self {
+        Procedural {
+            pattern,
+            space,
+            scale: 1.0,
+            colors: [Vec3::zero(), Vec3::one()],
+        }
+    }
+
+    pub fn sample(&self, at: &Lookup) -> Vec3 {
+        let p = match self.space {
+            Space::Uv => Vec3::new(at.uv.x, at.uv.y, 0.0),
+            Space::World => at.world,
+            Space::Object => at.object,
+        };
+        let t = self.pattern.value(p * self.scale);
+        self.colors[0].lerp(self.colors[1], t)
+    }
+}
+
+// Integer lattice hash.
+fn hash(x: i32, y: i32, z: i32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^ (h >> 15)
+}
+
+// Dot product of `d` with one of the 12 gradients of improved Perlin noise,
+// picked by `h`.
+fn gradient(h: u32, d: Vec3) -> f32 {
+    match h % 12 {
+        0 => d.x + d.y,
+        1 => d.y - d.x,
+        2 => d.x - d.y,
+        3 => -d.x - d.y,
+        4 => d.x + d.z,
+        5 => d.z - d.x,
+        6 => d.x - d.z,
+        7 => -d.x - d.z,
+        8 => d.y + d.z,
+        9 => d.z - d.y,
+        10 => d.y - d.z,
+        _ => -d.y - d.z,
+    }
+}
+
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+// Perlin gradient noise, roughly in [-1, 1], zero on the integer lattice.
+pub fn perlin(p: Vec3) -> f32 {
+    let cell = Vec3::new(p.x.floor(), p.y.floor(), p.z.floor());
+    // far from the origin the cells saturate, and their neighbours wrap
+    let (i, j, k) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let f = p - cell;
+    let corner = |dx: i32, dy: i32, dz: i32| {
+        let d = f - Vec3::new(dx as f32, dy as f32, dz as f32);
+        gradient(
+            hash(i.wrapping_add(dx), j.wrapping_add(dy), k.wrapping_add(dz)),
+            d,
+        )
+    };
+    let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+    let x00 = corner(0, 0, 0).lerp(corner(1, 0, 0), u);
+    let x10 = corner(0, 1, 0).lerp(corner(1, 1, 0), u);
+    let x01 = corner(0, 0, 1).lerp(corner(1, 0, 1), u);
+    let x11 = corner(0, 1, 1).lerp(corner(1, 1, 1), u);
+    let y0 = x00.lerp(x10, v);
+    let y1 = x01.lerp(x11, v);
+    y0.lerp(y1, w)
+}
+
+// Octaves past this one are below the precision of the first one, and their
+// frequencies would overflow.
+pub const MAX_OCTAVES: u32 = 24;
+
+// Sum of `octaves` octaves of noise (at most `MAX_OCTAVES`), each at twice
+// the frequency and half the amplitude of the previous one.
+pub fn fbm(p: Vec3, octaves: u32) -> f32 {
+    (0..octaves.min(MAX_OCTAVES))
+        .map(|o| {
+            let f = (1 << o) as f32;
+            perlin(p * f) / f
+        })
+        .sum()
+}
+
+// Like `fbm`, summing the absolute values of the octaves.
+pub fn turbulence(p: Vec3, octaves: u32) -> f32 {
+    (0..octaves.min(MAX_OCTAVES))
+        .map(|o| {
+            let f = (1 << o) as f32;
+            perlin(p * f).abs() / f
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ultraviolet::vec::Vec2;
+
+    #[test]
+    fn test_noise() {
+        assert_eq!(perlin(Vec3::new(3.0, -2.0, 7.0)), 0.0);
+        let mut values = Vec::new();
+        for i in 0..1000 {
+            let p = Vec3::new(i as f32 * 0.137, i as f32 * 0.071, i as f32 * -0.093);
+            let n = perlin(p);
+            assert!(n.abs() <= 1.1);
+            // continuous
+            assert!((perlin(p + Vec3::broadcast(1e-4)) - n).abs() < 1e-2);
+            values.push(n);
+        }
+        assert!(values.iter().any(|n| *n > 0.2) && values.iter().any(|n| *n < -0.2));
+        assert!(turbulence(Vec3::new(0.3, 0.6, 0.9), 4) >= 0.0);
+        let p = Vec3::new(0.3, 0.6, 0.9);
+        assert_eq!(fbm(p, 40), fbm(p, MAX_OCTAVES));
+        assert_eq!(turbulence(p, u32::MAX), turbulence(p, MAX_OCTAVES));
+        for far in [1e9, 3e9, -3e9, 1e30] {
+            let p = Vec3::new(far, 0.5, -far);
+            assert!(perlin(p).is_finite() && fbm(p, MAX_OCTAVES).is_finite());
+        }
+
+        let wood = Pattern::Wood {
+            octaves: 3,
+            turbulence: 0.5,
+        };
+        let marble = Pattern::Marble {
+            octaves: 3,
+            turbulence: 5.0,
+        };
+        for p in [Vec3::new(0.3, 0.1, 2.7), Vec3::new(-4.2, 1.5, 0.6)] {
+            for pattern in [wood, marble, Pattern::Noise { octaves: 5 }] {
+                let t = pattern.value(p);
+                assert!((0.0..=1.0).contains(&t));
+            }
+        }
+    }
+
+    #[test]
+    fn test_patterns() {
+        let at = Lookup {
+            uv: Vec2::new(0.25, 0.75),
+            world: Vec3::new(1.5, -5.0, 0.5),
+            object: Vec3::new(0.5, 0.0, 0.5),
+        };
+        let mut checker = Procedural::new(Pattern::Checker, Space::World);
+        // on the plane y = -5, even when rounding puts the hit slightly
+        // below it, the cells alternate along x and z only
+        assert_eq!(checker.sample(&at), Vec3::zero());
+        let below = Lookup {
+            world: Vec3::new(1.5, -5.000001, 0.5),
+            ..at
+        };
+        assert_eq!(checker.sample(&below), Vec3::zero());
+        let next = Lookup {
+            world: Vec3::new(2.5, -5.0, 0.5),
+            ..at
+        };
+        assert_eq!(checker.sample(&next), Vec3::one());
+        checker.space = Space::Object;
+        assert_eq!(checker.sample(&at), Vec3::zero());
+        checker.space = Space::Uv;
+        checker.scale = 2.0;
+        assert_eq!(checker.sample(&at), Vec3::one());
+
+        // texcoord space is the face z = 0 of the cells
+        let mut grid = Procedural::new(Pattern::Grid { width: 0.1 }, Space::Uv);
+        grid.colors = [Vec3::unit_x(), Vec3::unit_y()];
+        assert_eq!(grid.sample(&at), Vec3::unit_x());
+        let line = Lookup {
+            uv: Vec2::new(0.98, 0.5),
+            ..at
+        };
+        assert_eq!(grid.sample(&line), Vec3::unit_y());
+        grid.space = Space::World;
+        assert_eq!(grid.sample(&at), Vec3::unit_x());
+        assert_eq!(grid.sample(&line), Vec3::unit_x());
+    }
+}
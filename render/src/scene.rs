@@ -1,9 +1,14 @@
 use crate::bih::BihState;
-use crate::types::{Light, Material};
+use crate::texture::{Lookup, Texture};
+use crate::types::{Hit, Light, Material};
 use crate::{aabb::Aabb, triaccel};
+use std::collections::HashMap;
+use std::io::Read;
 use ultraviolet::rotor::Rotor3;
-use ultraviolet::vec::Vec3;
-use wfront::loader::{Triangle as Tri, V3};
+use ultraviolet::vec::{Vec2, Vec3};
+use ultraviolet::{Lerp, Slerp};
+use wfront::bmesh::MeshView;
+use wfront::loader::{Group, MtlMaterial, Triangle as Tri, NO_TEXCOORDS, V3};
 
 pub type Vertex = Vec3;
 
@@ -15,24 +20,43 @@ pub struct Triangle {
     pub mat: u32,
 }
 
+// Stable reference to an object of a scene.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ObjectHandle(usize);
+
+#[derive(Clone)]
 pub struct Object {
     pub rot: Rotor3,
     pub pos: Vec3,
     pub tstart: usize, // inclusive
     pub tstop: usize,  // inclusive
+    // position and rotation at shutter close, see `set_motion`
+    pub motion: Option<(Vec3, Rotor3)>,
 }
 
 pub struct Scene {
     pub ambient: Vec3,
     pub lights: Vec<Light>,
     pub materials: Vec<Material>,
+    pub textures: Vec<Texture>, // referenced by the materials
     pub vbuffer: Vec<Vertex>,
     pub tbuffer: Vec<Triangle>,
     pub nbuffer: Vec<Vec3>,
+    pub uvbuffer: Vec<[Vec2; 3]>, // texcoords of the corners, zero when the mesh has none
     pub objects: Vec<Object>,
     bboxes: Vec<Aabb>,
     pub triaccels: Vec<triaccel::TriAccel>,
     pub global: Aabb,
+    // `vbuffer` holds the world-space vertices, i.e. `rest` with the
+    // transform of the owning object applied
+    rest: Vec<Vertex>,
+    handles: Vec<Option<usize>>, // index in `objects` of each handle
+    // world-space vertices at shutter close, how far from the segment to
+    // there each vertex may stray (rotating objects follow arcs), and which
+    // triangles move; all empty while nothing does
+    motion: Vec<Vertex>,
+    sway: Vec<f32>,
+    moving: Vec<bool>,
 }
 
 pub struct TriangleIterator<'a> {
@@ -66,16 +90,59 @@ impl Scene {
             ambient: Vec3::zero(),
             lights: Vec::new(),
             materials: Vec::new(),
+            textures: Vec::new(),
             vbuffer: Vec::new(),
             tbuffer: Vec::new(),
             nbuffer: Vec::new(),
+            uvbuffer: Vec::new(),
             objects: Vec::new(),
             triaccels: Vec::new(),
             bboxes: Vec::new(),
             global: crate::aabb::EMPTY,
+            rest: Vec::new(),
+            handles: Vec::new(),
+            motion: Vec::new(),
+            sway: Vec::new(),
+            moving: Vec::new(),
         }
     }
 
+    // Rebuilds a scene from its geometry buffers, recomputing the derived
+    // per-triangle data (boxes and TriAccels). `vbuffer` is in world space;
+    // the object-space vertices are recovered from the objects' transforms.
+    pub fn from_geometry(
+        vbuffer: Vec<Vertex>,
+        tbuffer: Vec<Triangle>,
+        nbuffer: Vec<Vec3>,
+        uvbuffer: Vec<[Vec2; 3]>,
+        objects: Vec<Object>,
+    ) -> Self {
+        let mut scene = Scene::new();
+        for t in tbuffer.iter() {
+            let aabb = triangle_aabb(&vbuffer, t);
+            let p0 = vbuffer[t.t0 as usize];
+            let p1 = vbuffer[t.t1 as usize];
+            let p2 = vbuffer[t.t2 as usize];
+            scene.global = crate::aabb::join(&aabb, &scene.global);
+            scene.bboxes.push(aabb);
+            scene.triaccels.push(triaccel::precompute(p0, p1, p2));
+        }
+        scene.rest = vbuffer.clone();
+        for obj in objects.iter() {
+            let inverse = obj.rot.reversed();
+            for v in object_vertices(&tbuffer, obj) {
+                scene.rest[v] = inverse * (vbuffer[v] - obj.pos);
+            }
+        }
+        scene.vbuffer = vbuffer;
+        scene.tbuffer = tbuffer;
+        scene.nbuffer = nbuffer;
+        scene.uvbuffer = uvbuffer;
+        scene.handles = (0..objects.len()).map(Some).collect();
+        scene.objects = objects;
+        scene
+    }
+
     pub fn iter_triangles(&self) -> TriangleIterator {
         TriangleIterator {
             current: 0,
@@ -89,11 +156,14 @@ impl Scene {
         vbuffer: &mut Vec<Vertex>,
         tbuffer: &mut Vec<Triangle>,
         nbuffer: &mut Vec<Vec3>,
-    ) -> Object {
+        uvbuffer: &mut Vec<[Vec2; 3]>,
+    ) -> ObjectHandle {
         let vcount = self.vbuffer.len();
 
+        self.rest.extend_from_slice(vbuffer);
         self.vbuffer.append(vbuffer);
         self.nbuffer.append(nbuffer);
+        self.uvbuffer.append(uvbuffer);
 
         let tstart = self.tbuffer.len();
         let tstop = tstart + tbuffer.len() - 1;
@@ -111,48 +181,101 @@ impl Scene {
             self.bboxes.push(aabb);
             self.triaccels.push(triaccel::precompute(p0, p1, p2));
         }
+        self.pad_motion();
 
-        Object {
+        let obj = Object {
             rot: Rotor3::identity(),
             pos: Vec3::zero(),
             tstart,
             tstop,
-        }
+            motion: None,
+        };
+        self.push_object(obj)
     }
 
-    pub fn add_wavefront(&mut self, shift: Vec3, fname: &str) -> Object {
-        let mesh = wfront::loader::load(fname);
-
-        let mut tbuffer: Vec<Triangle> = Vec::new();
-        let mut vbuffer: Vec<Vec3> = Vec::new();
-
-        println!("Loading {fname}");
+    fn push_object(&mut self, obj: Object) -> ObjectHandle {
+        self.objects.push(obj);
+        self.handles.push(Some(self.objects.len() - 1));
+        ObjectHandle(self.handles.len() - 1)
+    }
 
-        {
-            println!(
-                "vertices = {}; triangles = {}",
-                mesh.vertices.len(),
-                mesh.triangles.len()
-            );
+    // Adds a Wavefront or binary mesh file; see `add_mesh_file`.
+    pub fn add_wavefront(&mut self, shift: Vec3, fname: &str) -> std::io::Result<ObjectHandle> {
+        self.add_mesh_file(shift, fname)
+    }
 
-            let mut triangles: Vec<Triangle> = mesh
-                .triangles
-                .iter()
-                .map(|Tri(t0, t1, t2)| Triangle {
-                    t0: (*t0 - 1),
-                    t1: (*t1 - 1),
-                    t2: (*t2 - 1),
-                    mat: 0,
+    // Like `add_wavefront`, but creates one object per OBJ group, i.e. per
+    // run of triangles sharing an `o` and `g` name. Vertices on the borders
+    // between groups are duplicated, so that the objects move independently.
+    // Files without groups yield a single object.
+    pub fn add_wavefront_groups(
+        &mut self,
+        shift: Vec3,
+        fname: &str,
+    ) -> std::io::Result<Vec<ObjectHandle>> {
+        let mut mesh = load_mesh_file(fname)?;
+        if mesh.triangles.is_empty() {
+            return Err(no_triangles(fname));
+        }
+        let mut groups = mesh.groups.clone();
+        // runs differing only by their smoothing group belong to one object
+        groups.dedup_by(|next, prev| {
+            let same = (&next.object, &next.name) == (&prev.object, &prev.name);
+            if same {
+                prev.stop = next.stop;
+            }
+            same
+        });
+        if groups.len() <= 1 {
+            return Ok(vec![self.add_mesh(shift, mesh)]);
+        }
+        split_vertices(&mut mesh, &groups);
+        self.add_mesh(shift, mesh);
+        self.handles.pop();
+        let whole = self.objects.pop().unwrap();
+        Ok(groups
+            .iter()
+            .map(|group| {
+                self.push_object(Object {
+                    tstart: whole.tstart + group.start,
+                    tstop: whole.tstart + group.stop - 1,
+                    ..whole.clone()
                 })
-                .collect();
-
-            tbuffer.append(&mut triangles);
+            })
+            .collect())
+    }
 
-            for V3(x, y, z) in mesh.vertices {
-                let v = Vec3::new(x, y, z);
-                vbuffer.push(shift + v);
+    // Adds a mesh in any supported format (see `load_mesh_file`). Files
+    // without triangles are rejected, as objects cannot be empty. Materials
+    // the mesh uses are added too (see `append_materials`).
+    pub fn add_mesh_file(&mut self, shift: Vec3, fname: &str) -> std::io::Result<ObjectHandle> {
+        // binary meshes are read in place, without a copy into a `Mesh`
+        if mesh_format(fname)? == MeshFormat::Binary {
+            let mapped = wfront::bmesh::map(fname)?;
+            let view = mapped.view()?;
+            if view.triangles.is_empty() {
+                return Err(no_triangles(fname));
             }
+            return Ok(self.add_mesh_view(shift, &view));
+        }
+        let mesh = load_mesh_file(fname)?;
+        if mesh.triangles.is_empty() {
+            return Err(no_triangles(fname));
         }
+        Ok(self.add_mesh(shift, mesh))
+    }
+
+    pub(crate) fn add_mesh(&mut self, shift: Vec3, mesh: wfront::loader::Mesh) -> ObjectHandle {
+        self.add_mesh_view(shift, &MeshView::from(&mesh))
+    }
+
+    fn add_mesh_view(&mut self, shift: Vec3, mesh: &MeshView) -> ObjectHandle {
+        assert!(
+            !mesh.triangles.is_empty(),
+            "add_mesh: mesh without triangles"
+        );
+        let (mut vbuffer, mut tbuffer, mut uvbuffer) = mesh_buffers(shift, mesh);
+        self.append_materials(mesh, &mut tbuffer);
 
         let mut nbuffer = tbuffer
             .iter()
@@ -164,30 +287,622 @@ impl Scene {
             })
             .collect();
 
-        self.add_object(&mut vbuffer, &mut tbuffer, &mut nbuffer)
+        self.add_object(&mut vbuffer, &mut tbuffer, &mut nbuffer, &mut uvbuffer)
     }
 
-    pub fn refresh_triaccel(&mut self, obj: &Object) {
+    // The material ids of a mesh index materials of its own: appends white
+    // ones to `materials`, MTL files not being read, and offsets the ids of
+    // `tbuffer` to them. Meshes without ids use the scene's first material.
+    fn append_materials(&mut self, mesh: &MeshView, tbuffer: &mut [Triangle]) {
+        if let Some(count) = mesh.materials.iter().max().map(|m| *m as usize + 1) {
+            let base = self.materials.len() as u32;
+            self.materials
+                .extend((0..count).map(|_| crate::types::default_material(Vec3::one())));
+            for t in tbuffer.iter_mut() {
+                t.mat += base;
+            }
+        }
+    }
+
+    // Index in `objects` of the object owning triangle `tri`.
+    pub fn object_of_triangle(&self, tri: u32) -> Option<usize> {
+        let tri = tri as usize;
+        let i = self.objects.partition_point(|obj| obj.tstop < tri);
+        match self.objects.get(i) {
+            Some(obj) if obj.tstart <= tri => Some(i),
+            _ => None,
+        }
+    }
+
+    // The geometry the renderer sees, as a mesh: vertices duplicated where
+    // objects share them, and one group per
+    // object (named `object{index}`). Materials are named `material{index}`
+    // and also returned in MTL form. Texcoords are included when some
+    // triangle has non-zero ones.
+    pub fn to_mesh(&self) -> (wfront::loader::Mesh, Vec<MtlMaterial>) {
+        let mut mesh = wfront::loader::Mesh {
+            vertices: Vec::new(),
+            normals: Vec::new(),
+            texcoords: Vec::new(),
+            colors: Vec::new(),
+            triangles: Vec::new(),
+            uv_triangles: Vec::new(),
+            materials: Vec::new(),
+            material_names: Vec::new(),
+            groups: Vec::new(),
+        };
+        // 1-based index in `mesh.vertices` of each (object, vertex)
+        let mut remap: HashMap<(Option<usize>, u32), u32> = HashMap::new();
+        // and in `mesh.texcoords` of each texcoord
+        let textured = self.uvbuffer.iter().flatten().any(|uv| *uv != Vec2::zero());
+        let mut uv_remap: HashMap<[u32; 2], u32> = HashMap::new();
+        for (i, t) in self.tbuffer.iter().enumerate() {
+            let owner = self.object_of_triangle(i as u32);
+            if mesh.groups.last().map(|g| &g.object) != Some(&object_name(owner)) {
+                if let Some(last) = mesh.groups.last_mut() {
+                    last.stop = i;
+                }
+                mesh.groups.push(Group {
+                    object: object_name(owner),
+                    name: String::new(),
+                    smoothing: 0,
+                    start: i,
+                    stop: i,
+                });
+            }
+            let mut corner = |v: u32| {
+                *remap.entry((owner, v)).or_insert_with(|| {
+                    let p = self.vbuffer[v as usize];
+                    mesh.vertices.push(V3(p.x, p.y, p.z));
+                    mesh.vertices.len() as u32
+                })
+            };
+            let triangle = Tri(corner(t.t0), corner(t.t1), corner(t.t2));
+            mesh.triangles.push(triangle);
+            mesh.materials.push(t.mat);
+            if textured {
+                let mut corner = |uv: Vec2| {
+                    *uv_remap
+                        .entry([uv.x.to_bits(), uv.y.to_bits()])
+                        .or_insert_with(|| {
+                            mesh.texcoords.push(V3(uv.x, uv.y, 0.0));
+                            mesh.texcoords.len() as u32
+                        })
+                };
+                let [uv0, uv1, uv2] = self.uvbuffer[i];
+                mesh.uv_triangles
+                    .push(Tri(corner(uv0), corner(uv1), corner(uv2)));
+            }
+        }
+        if let Some(last) = mesh.groups.last_mut() {
+            last.stop = self.tbuffer.len();
+        }
+
+        let count = self
+            .tbuffer
+            .iter()
+            .map(|t| t.mat as usize + 1)
+            .max()
+            .unwrap_or(0)
+            .max(self.materials.len());
+        mesh.material_names = (0..count).map(|i| format!("material{i}")).collect();
+        let materials = self
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(i, m)| MtlMaterial {
+                name: format!("material{i}"),
+                diffuse: V3(m.m_color.x, m.m_color.y, m.m_color.z),
+                specular: V3(m.m_specular, m.m_specular, m.m_specular),
+                shininess: m.m_shininess,
+            })
+            .collect();
+        (mesh, materials)
+    }
+
+    // Writes `to_mesh` as OBJ (with an MTL library) or binary PLY, by
+    // extension.
+    pub fn export(&self, path: &str) -> std::io::Result<()> {
+        let (mesh, materials) = self.to_mesh();
+        match MeshFormat::from_extension(path) {
+            Some(MeshFormat::Wavefront) => wfront::loader::save(path, &mesh, &materials),
+            Some(MeshFormat::Ply) => wfront::ply::save(path, &mesh, wfront::ply::Format::BinaryLe),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{path}: can only export .obj or .ply files"),
+            )),
+        }
+    }
+
+    fn index(&self, handle: ObjectHandle) -> usize {
+        self.handles[handle.0].expect("stale object handle")
+    }
+
+    pub fn object(&self, handle: ObjectHandle) -> &Object {
+        &self.objects[self.index(handle)]
+    }
+
+    // Handles of the live objects, in the order of `objects`.
+    pub fn handles(&self) -> Vec<ObjectHandle> {
+        let mut handles: Vec<(usize, ObjectHandle)> = self
+            .handles
+            .iter()
+            .enumerate()
+            .filter_map(|(h, i)| i.map(|i| (i, ObjectHandle(h))))
+            .collect();
+        handles.sort_unstable();
+        handles.into_iter().map(|(_, h)| h).collect()
+    }
+
+    // Moves an object, updating its vertices, normals, boxes and TriAccels,
+    // and the scene's bounding box. A BIH built before the move must then be
+    // refitted (`refit_bih`) or rebuilt (`compute_bih`). The object stops
+    // moving (see `set_motion`).
+    pub fn set_transform(&mut self, handle: ObjectHandle, pos: Vec3, rot: Rotor3) {
+        let i = self.index(handle);
+        self.objects[i].pos = pos;
+        self.objects[i].rot = rot;
+        self.objects[i].motion = None;
+        let obj = self.objects[i].clone();
+        for v in object_vertices(&self.tbuffer, &obj) {
+            self.vbuffer[v] = pos + rot * self.rest[v];
+            if let Some(end) = self.motion.get_mut(v) {
+                *end = self.vbuffer[v];
+                self.sway[v] = 0.0;
+            }
+        }
+        for i in obj.tstart..=obj.tstop {
+            if let Some(moving) = self.moving.get_mut(i) {
+                *moving = false;
+            }
+            self.refresh_triangle(i);
+        }
+        self.refresh_global();
+    }
+
+    // Gives an object a transform at shutter close, its current transform
+    // holding at shutter open, for motion blur. At the time of each ray, the
+    // position is interpolated linearly and the rotation spherically, so
+    // that rotating objects keep their shape. As after `set_transform`, BIHs
+    // of the scene must be refitted or rebuilt.
+    pub fn set_motion(&mut self, handle: ObjectHandle, pos: Vec3, rot: Rotor3) {
+        let i = self.index(handle);
+        let obj = self.objects[i].clone();
+        let vertices = object_vertices(&self.tbuffer, &obj);
+        let end: Vec<Vertex> = vertices.iter().map(|v| pos + rot * self.rest[*v]).collect();
+        // Vertices turn about a fixed axis by the angle between the
+        // rotations, twice the angle whose cosine is their dot product, and
+        // their arcs stray from the segments by at most their sagitta.
+        let sagitta = 1.0 - obj.rot.dot(rot).abs().min(1.0);
+        let sway: Vec<f32> = vertices
+            .iter()
+            .map(|v| self.rest[*v].mag() * sagitta)
+            .collect();
+        self.objects[i].motion = Some((pos, rot));
+        self.move_vertices(&obj, &vertices, &end, &sway);
+    }
+
+    // Motion of a deforming object: the world-space position at shutter
+    // close of each of its vertices, in the order of `object_vertices`.
+    // Vertices move linearly in between.
+    pub fn set_vertex_motion(&mut self, handle: ObjectHandle, end: &[Vertex]) {
+        let i = self.index(handle);
+        self.objects[i].motion = None;
+        let obj = self.objects[i].clone();
+        let vertices = object_vertices(&self.tbuffer, &obj);
+        assert_eq!(end.len(), vertices.len(), "set_vertex_motion: vertex count");
+        self.move_vertices(&obj, &vertices, end, &vec![0.0; end.len()]);
+    }
+
+    fn move_vertices(&mut self, obj: &Object, vertices: &[usize], end: &[Vertex], sway: &[f32]) {
+        if self.motion.is_empty() {
+            self.motion = self.vbuffer.clone();
+            self.sway = vec![0.0; self.vbuffer.len()];
+            self.moving = vec![false; self.tbuffer.len()];
+        }
+        for (i, v) in vertices.iter().enumerate() {
+            self.motion[*v] = end[i];
+            self.sway[*v] = sway[i];
+        }
         for i in obj.tstart..=obj.tstop {
-            let t = self.tbuffer[i];
-            let p0 = obj.pos + obj.rot * self.vbuffer[t.t0 as usize];
-            let p1 = obj.pos + obj.rot * self.vbuffer[t.t1 as usize];
-            let p2 = obj.pos + obj.rot * self.vbuffer[t.t2 as usize];
-            self.triaccels[i] = triaccel::precompute(p0, p1, p2);
+            self.moving[i] = true;
+            self.refresh_triangle(i);
+        }
+        self.refresh_global();
+    }
+
+    // Stops all objects at their shutter-open positions.
+    pub fn clear_motion(&mut self) {
+        let moving = std::mem::take(&mut self.moving);
+        self.motion.clear();
+        self.sway.clear();
+        for obj in self.objects.iter_mut() {
+            obj.motion = None;
+        }
+        for (i, _) in moving.into_iter().enumerate().filter(|(_, m)| *m) {
+            self.refresh_triangle(i);
+        }
+        self.refresh_global();
+    }
+
+    // Indices in `vbuffer` of the vertices of an object, sorted.
+    pub fn object_vertices(&self, handle: ObjectHandle) -> Vec<usize> {
+        object_vertices(&self.tbuffer, self.object(handle))
+    }
+
+    pub fn is_moving(&self, tri: usize) -> bool {
+        self.moving.get(tri).copied().unwrap_or(false)
+    }
+
+    // Corners of triangle `tri` at `time` (see `Ray::time`).
+    pub fn triangle_at(&self, tri: usize, time: f32) -> [Vertex; 3] {
+        let t = self.tbuffer[tri];
+        let corners = [t.t0, t.t1, t.t2].map(|v| v as usize);
+        if !self.is_moving(tri) {
+            return corners.map(|v| self.vbuffer[v]);
+        }
+        let obj = self
+            .object_of_triangle(tri as u32)
+            .map(|o| &self.objects[o]);
+        match obj.and_then(|obj| obj.motion.map(|end| (obj, end))) {
+            Some((obj, (end_pos, end_rot))) => {
+                let pos = obj.pos.lerp(end_pos, time);
+                let rot = obj.rot.slerp(end_rot, time).normalized();
+                corners.map(|v| pos + rot * self.rest[v])
+            }
+            None => corners.map(|v| self.vbuffer[v].lerp(self.motion[v], time)),
+        }
+    }
+
+    // Normal of triangle `tri` at `time`.
+    pub fn normal_at(&self, tri: usize, time: f32) -> Vec3 {
+        if !self.is_moving(tri) {
+            return self.nbuffer[tri];
+        }
+        let [p0, p1, p2] = self.triangle_at(tri, time);
+        (p1 - p0).cross(p2 - p0).normalized()
+    }
+
+    // Texcoords at a hit, interpolated from the corners of its triangle.
+    pub fn texcoord(&self, hit: &Hit) -> Vec2 {
+        let [uv0, uv1, uv2] = self.uvbuffer[hit.tri as usize];
+        uv0 * (1.0 - hit.u - hit.v) + uv1 * hit.u + uv2 * hit.v
+    }
+
+    // Where textures are looked up at a hit at `time`.
+    pub fn lookup(&self, hit: &Hit, time: f32) -> Lookup {
+        let t = self.tbuffer[hit.tri as usize];
+        let blend = |[p0, p1, p2]: [Vec3; 3]| p0 * (1.0 - hit.u - hit.v) + p1 * hit.u + p2 * hit.v;
+        let rest = [t.t0, t.t1, t.t2].map(|v| self.rest[v as usize]);
+        Lookup {
+            uv: self.texcoord(hit),
+            world: blend(self.triangle_at(hit.tri as usize, time)),
+            object: blend(rest),
+        }
+    }
+
+    // Box of a triangle over the whole shutter interval.
+    fn triangle_bounds(&self, t: &Triangle) -> Aabb {
+        let aabb = triangle_aabb(&self.vbuffer, t);
+        if self.motion.is_empty() {
+            return aabb;
+        }
+        let mut aabb = crate::aabb::join(&aabb, &triangle_aabb(&self.motion, t));
+        let sway = [t.t0, t.t1, t.t2]
+            .map(|v| self.sway[v as usize])
+            .into_iter()
+            .fold(0.0, f32::max);
+        aabb.mins -= Vec3::broadcast(sway);
+        aabb.maxs += Vec3::broadcast(sway);
+        aabb
+    }
+
+    // Extends the motion buffers over vertices and triangles added since
+    // they were allocated, which hold still.
+    fn pad_motion(&mut self) {
+        if self.motion.is_empty() {
+            return;
+        }
+        let n = self.motion.len();
+        self.motion.extend_from_slice(&self.vbuffer[n..]);
+        self.sway.resize(self.vbuffer.len(), 0.0);
+        self.moving.resize(self.tbuffer.len(), false);
+    }
+
+    // Recomputes the normal, box and TriAccel of a triangle.
+    fn refresh_triangle(&mut self, i: usize) {
+        let t = self.tbuffer[i];
+        let p0 = self.vbuffer[t.t0 as usize];
+        let p1 = self.vbuffer[t.t1 as usize];
+        let p2 = self.vbuffer[t.t2 as usize];
+        self.nbuffer[i] = (p1 - p0).cross(p2 - p0).normalized();
+        self.bboxes[i] = self.triangle_bounds(&t);
+        self.triaccels[i] = triaccel::precompute(p0, p1, p2);
+    }
+
+    fn refresh_global(&mut self) {
+        self.global = self
+            .bboxes
+            .iter()
+            .fold(crate::aabb::EMPTY, |acc, b| crate::aabb::join(b, &acc));
+    }
+
+    // Removes an object with its triangles, and the vertices that no other
+    // object uses. Triangle and vertex indices change: BIHs of the scene
+    // must be rebuilt (`compute_bih`). The handle becomes stale.
+    pub fn remove_object(&mut self, handle: ObjectHandle) {
+        let i = self.index(handle);
+        let obj = self.objects.remove(i);
+        self.handles[handle.0] = None;
+        for index in self.handles.iter_mut().flatten() {
+            if *index > i {
+                *index -= 1;
+            }
+        }
+        self.splice_triangles(&obj, Vec::new(), Vec::new());
+        self.compact_vertices();
+        self.refresh_global();
+    }
+
+    // Replaces the geometry of an object by a non-empty mesh, keeping its
+    // handle and transform. As with `remove_object`, BIHs of the scene
+    // must be rebuilt.
+    pub fn replace_mesh(&mut self, handle: ObjectHandle, mesh: wfront::loader::Mesh) {
+        assert!(!mesh.triangles.is_empty(), "replace_mesh: empty mesh");
+        let i = self.index(handle);
+        let obj = self.objects[i].clone();
+        let view = MeshView::from(&mesh);
+        let (rest, mut tbuffer, uvbuffer) = mesh_buffers(Vec3::zero(), &view);
+        self.append_materials(&view, &mut tbuffer);
+        let vcount = self.vbuffer.len() as u32;
+        for t in tbuffer.iter_mut() {
+            t.t0 += vcount;
+            t.t1 += vcount;
+            t.t2 += vcount;
+        }
+        self.vbuffer
+            .extend(rest.iter().map(|v| obj.pos + obj.rot * *v));
+        self.rest.extend(rest);
+        self.objects[i].tstop = obj.tstart + tbuffer.len() - 1;
+        // the new triangles hold still
+        self.objects[i].motion = None;
+        self.pad_motion();
+        self.splice_triangles(&obj, tbuffer, uvbuffer);
+        self.compact_vertices();
+        self.refresh_global();
+    }
+
+    // Replaces the triangles of `obj` by `triangles`, whose vertices are in
+    // place, and shifts the objects after it.
+    fn splice_triangles(&mut self, obj: &Object, triangles: Vec<Triangle>, uvs: Vec<[Vec2; 3]>) {
+        let range = obj.tstart..=obj.tstop;
+        let (old, new) = (obj.tstop + 1 - obj.tstart, triangles.len());
+        self.tbuffer.splice(range.clone(), triangles);
+        self.uvbuffer.splice(range.clone(), uvs);
+        self.nbuffer
+            .splice(range.clone(), std::iter::repeat_n(Vec3::zero(), new));
+        self.bboxes
+            .splice(range.clone(), std::iter::repeat_n(crate::aabb::EMPTY, new));
+        self.triaccels.splice(
+            range.clone(),
+            std::iter::repeat_with(Default::default).take(new),
+        );
+        if !self.moving.is_empty() {
+            self.moving.splice(range, std::iter::repeat_n(false, new));
+        }
+        for i in obj.tstart..obj.tstart + new {
+            self.refresh_triangle(i);
+        }
+        for other in self.objects.iter_mut() {
+            if other.tstart > obj.tstop {
+                other.tstart = other.tstart + new - old;
+                other.tstop = other.tstop + new - old;
+            }
+        }
+    }
+
+    // Drops the vertices no triangle uses.
+    fn compact_vertices(&mut self) {
+        let mut used = vec![false; self.vbuffer.len()];
+        for t in self.tbuffer.iter() {
+            used[t.t0 as usize] = true;
+            used[t.t1 as usize] = true;
+            used[t.t2 as usize] = true;
+        }
+        let mut remap = vec![0; self.vbuffer.len()];
+        let mut count = 0;
+        for (v, used) in used.into_iter().enumerate() {
+            if used {
+                self.vbuffer[count] = self.vbuffer[v];
+                self.rest[count] = self.rest[v];
+                if !self.motion.is_empty() {
+                    self.motion[count] = self.motion[v];
+                    self.sway[count] = self.sway[v];
+                }
+                remap[v] = count as u32;
+                count += 1;
+            }
+        }
+        self.vbuffer.truncate(count);
+        self.rest.truncate(count);
+        if !self.motion.is_empty() {
+            self.motion.truncate(count);
+            self.sway.truncate(count);
+        }
+        for t in self.tbuffer.iter_mut() {
+            t.t0 = remap[t.t0 as usize];
+            t.t1 = remap[t.t1 as usize];
+            t.t2 = remap[t.t2 as usize];
+        }
+    }
+
+    pub fn set_position(&mut self, handle: ObjectHandle, pos: Vec3) {
+        let rot = self.object(handle).rot;
+        self.set_transform(handle, pos, rot);
+    }
+
+    pub fn set_orientation(&mut self, handle: ObjectHandle, rot: Rotor3) {
+        let pos = self.object(handle).pos;
+        self.set_transform(handle, pos, rot);
+    }
+
+    // Gives all the triangles of an object the material `mat`, an index in
+    // `materials`.
+    pub fn set_material(&mut self, handle: ObjectHandle, mat: u32) {
+        let obj = self.object(handle).clone();
+        for t in &mut self.tbuffer[obj.tstart..=obj.tstop] {
+            t.mat = mat;
         }
     }
 }
 
-impl Object {
-    pub fn set_orientation(&mut self, rot: Rotor3) {
-        self.rot = rot;
+// Vertices, 0-based triangles and corner texcoords of a mesh, shifted.
+fn mesh_buffers(shift: Vec3, mesh: &MeshView) -> (Vec<Vertex>, Vec<Triangle>, Vec<[Vec2; 3]>) {
+    let texcoord = |i: u32| match mesh.texcoords.get(i as usize - 1) {
+        Some(V3(u, v, _)) => Vec2::new(*u, *v),
+        None => Vec2::zero(),
+    };
+    let uvbuffer = (0..mesh.triangles.len())
+        .map(|i| match mesh.texcoord_indices(i) {
+            Some(Tri(t0, t1, t2)) => [texcoord(t0), texcoord(t1), texcoord(t2)],
+            None => [Vec2::zero(); 3],
+        })
+        .collect();
+    let tbuffer = mesh
+        .triangles
+        .iter()
+        .enumerate()
+        .map(|(i, Tri(t0, t1, t2))| Triangle {
+            t0: (*t0 - 1),
+            t1: (*t1 - 1),
+            t2: (*t2 - 1),
+            mat: mesh.materials.get(i).copied().unwrap_or(0),
+        })
+        .collect();
+    let vbuffer = mesh
+        .vertices
+        .iter()
+        .map(|V3(x, y, z)| shift + Vec3::new(*x, *y, *z))
+        .collect();
+    (vbuffer, tbuffer, uvbuffer)
+}
+
+// Indices of the vertices used by an object, sorted.
+fn object_vertices(tbuffer: &[Triangle], obj: &Object) -> Vec<usize> {
+    let mut vertices: Vec<usize> = tbuffer[obj.tstart..=obj.tstop]
+        .iter()
+        .flat_map(|t| [t.t0 as usize, t.t1 as usize, t.t2 as usize])
+        .collect();
+    vertices.sort_unstable();
+    vertices.dedup();
+    vertices
+}
+
+// Gives each group vertices of its own. Texcoords given per vertex are
+// resolved into `uv_triangles` first, as the triangles stop indexing them.
+fn split_vertices(mesh: &mut wfront::loader::Mesh, groups: &[Group]) {
+    if mesh.uv_triangles.is_empty() && !mesh.texcoords.is_empty() {
+        mesh.uv_triangles = (0..mesh.triangles.len())
+            .map(|i| mesh.texcoord_indices(i).unwrap_or(NO_TEXCOORDS))
+            .collect();
+    }
+    let mut vertices = Vec::new();
+    for group in groups {
+        // 1-based index in `vertices` of each vertex of the group
+        let mut remap: HashMap<u32, u32> = HashMap::new();
+        for Tri(t0, t1, t2) in mesh.triangles[group.start..group.stop].iter_mut() {
+            for v in [t0, t1, t2] {
+                let old = *v;
+                *v = *remap.entry(old).or_insert_with(|| {
+                    vertices.push(mesh.vertices[old as usize - 1]);
+                    vertices.len() as u32
+                });
+            }
+        }
+    }
+    mesh.vertices = vertices;
+}
+
+fn object_name(object: Option<usize>) -> String {
+    match object {
+        Some(i) => format!("object{i}"),
+        None => String::new(),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MeshFormat {
+    Wavefront,
+    Binary,
+    Ply,
+    Stl,
+}
+
+impl MeshFormat {
+    pub fn from_extension(fname: &str) -> Option<MeshFormat> {
+        let ext = std::path::Path::new(fname).extension()?.to_str()?;
+        match ext.to_ascii_lowercase().as_str() {
+            "obj" | "wobj" => Some(MeshFormat::Wavefront),
+            "bmesh" => Some(MeshFormat::Binary),
+            "ply" => Some(MeshFormat::Ply),
+            "stl" => Some(MeshFormat::Stl),
+            _ => None,
+        }
     }
 
-    pub fn set_position(&mut self, pos: Vec3) {
-        self.pos = pos;
+    // Guesses the format of a file, defaulting to Wavefront.
+    pub fn from_magic(bytes: &[u8]) -> MeshFormat {
+        MeshFormat::from_header(bytes, bytes.len() as u64)
+    }
+
+    // Like `from_magic`, from the start of a file of `size` bytes.
+    pub fn from_header(header: &[u8], size: u64) -> MeshFormat {
+        if wfront::bmesh::is_bmesh(header) {
+            MeshFormat::Binary
+        } else if wfront::ply::is_ply(header) {
+            MeshFormat::Ply
+        } else if wfront::stl::is_stl_header(header, size) {
+            MeshFormat::Stl
+        } else {
+            MeshFormat::Wavefront
+        }
     }
 }
 
+// Bytes read from files without an extension to guess their format.
+const SNIFF_SIZE: u64 = 512;
+
+// Format of a mesh file, by extension, or else by the start of the file and
+// its size.
+pub fn mesh_format(fname: &str) -> std::io::Result<MeshFormat> {
+    if let Some(format) = MeshFormat::from_extension(fname) {
+        return Ok(format);
+    }
+    let file = std::fs::File::open(fname)?;
+    let size = file.metadata()?.len();
+    let mut header = Vec::new();
+    file.take(SNIFF_SIZE).read_to_end(&mut header)?;
+    Ok(MeshFormat::from_header(&header, size))
+}
+
+// Loads a mesh file, picking the parser by `mesh_format`. STL vertices are
+// welded.
+pub fn load_mesh_file(fname: &str) -> std::io::Result<wfront::loader::Mesh> {
+    match mesh_format(fname)? {
+        MeshFormat::Wavefront => Ok(wfront::loader::parse(&std::fs::read(fname)?)?),
+        MeshFormat::Binary => wfront::bmesh::load(fname),
+        MeshFormat::Ply => wfront::ply::load(fname),
+        MeshFormat::Stl => wfront::stl::load(fname, true),
+    }
+}
+
+pub(crate) fn no_triangles(fname: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{fname}: no triangles"),
+    )
+}
+
 pub fn triangle_aabb(vbuffer: &[Vertex], tri: &Triangle) -> Aabb {
     let p0 = vbuffer[tri.t0 as usize];
     let p1 = vbuffer[tri.t1 as usize];
@@ -201,11 +916,405 @@ impl crate::bih::Elt for Triangle {
     type T = Triangle;
     type State = Scene;
 
+    // Bounds moving triangles over the whole shutter interval.
     fn extents(state: &Self::State, elt: &Self::T) -> Aabb {
-        triangle_aabb(state.vbuffer.as_slice(), elt)
+        state.triangle_bounds(elt)
     }
 }
 
 pub fn compute_bih(scene: &Scene, leaf_bound: u32) -> BihState {
     crate::bih::alloc::<Triangle>(scene, &scene.tbuffer, leaf_bound)
 }
+
+// Updates a BIH of `scene` after objects moved, keeping its tree. Cheaper
+// than `compute_bih`, but traversal slows down as the objects move away
+// from where the tree was built.
+pub fn refit_bih(scene: &Scene, bih: &mut BihState) {
+    crate::bih::refit::<Triangle>(scene, &scene.tbuffer, bih)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_objects_per_group() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../wfront/corpus/groups.obj");
+        let mut scene = Scene::new();
+        let first = scene.add_wavefront(Vec3::zero(), path).unwrap();
+        let groups = scene.add_wavefront_groups(Vec3::zero(), path).unwrap();
+        let first = scene.object(first);
+        let groups: Vec<&Object> = groups.iter().map(|h| scene.object(*h)).collect();
+        assert_eq!((first.tstart, first.tstop), (0, 1));
+        assert_eq!(groups.len(), 2);
+        assert_eq!((groups[0].tstart, groups[0].tstop), (2, 2));
+        assert_eq!((groups[1].tstart, groups[1].tstop), (3, 3));
+        assert_eq!(scene.objects.len(), 3);
+        assert_eq!(scene.object_of_triangle(3), Some(2));
+        assert_eq!(scene.vbuffer.len(), 4 + 6);
+
+        let cube = concat!(env!("CARGO_MANIFEST_DIR"), "/../cube.obj");
+        assert_eq!(
+            scene
+                .add_wavefront_groups(Vec3::zero(), cube)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        let mut scene = Scene::new();
+        scene.add_wavefront(Vec3::zero(), path).unwrap();
+        let second = scene.add_wavefront(Vec3::new(3.0, 0.0, 0.0), path).unwrap();
+        scene
+            .materials
+            .push(crate::types::default_material(Vec3::one()));
+        let n = scene.tbuffer.len() / 2;
+        let before = scene.vbuffer[scene.tbuffer[n].t0 as usize];
+        scene.set_position(second, Vec3::new(0.0, 1.0, 0.0));
+        scene.tbuffer[n].mat = 1;
+
+        let dir = std::env::temp_dir().join(format!("bih-rs-test-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let obj_path = dir.join("export.obj");
+        let ply_path = dir.join("export.ply");
+        scene.export(obj_path.to_str().unwrap()).unwrap();
+        scene.export(ply_path.to_str().unwrap()).unwrap();
+        let obj = wfront::loader::load(obj_path.to_str().unwrap());
+        let ply = wfront::ply::load(ply_path.to_str().unwrap()).unwrap();
+        let mtl = std::fs::read_to_string(obj_path.with_extension("mtl")).unwrap();
+        assert!(mtl.starts_with("newmtl material0\n"));
+
+        let (mesh, _) = scene.to_mesh();
+        assert_eq!(obj, mesh);
+        assert_eq!(
+            (&ply.vertices, &ply.triangles),
+            (&mesh.vertices, &mesh.triangles)
+        );
+        assert_eq!(mesh.materials[n - 1..n + 2], [0, 1, 0]);
+        assert_eq!(mesh.material_names, vec!["material0", "material1"]);
+        assert_eq!(mesh.groups[1].object, "object1");
+        assert_eq!((mesh.groups[1].start, mesh.groups[1].stop), (n, 2 * n));
+
+        // the second sphere is shifted by 3 on x when added, then by 1 on y
+        let Tri(t0, _, _) = mesh.triangles[n];
+        let V3(x, y, z) = mesh.vertices[t0 as usize - 1];
+        assert_eq!(Vec3::new(x, y, z), before + Vec3::new(0.0, 1.0, 0.0));
+        assert!(scene.export("scene.stl").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_mesh_formats() {
+        assert_eq!(MeshFormat::from_extension("a/b.PLY"), Some(MeshFormat::Ply));
+        assert_eq!(
+            MeshFormat::from_extension("part.stl"),
+            Some(MeshFormat::Stl)
+        );
+        assert_eq!(MeshFormat::from_extension("noext"), None);
+        assert_eq!(MeshFormat::from_magic(b"ply\nformat"), MeshFormat::Ply);
+        assert_eq!(MeshFormat::from_magic(b"solid x\n"), MeshFormat::Stl);
+        assert_eq!(MeshFormat::from_magic(b"BMSH"), MeshFormat::Binary);
+        assert_eq!(MeshFormat::from_magic(b"v 0 0 0"), MeshFormat::Wavefront);
+
+        // the same quad from OBJ and PLY
+        let corpus = concat!(env!("CARGO_MANIFEST_DIR"), "/../wfront/corpus");
+        let mut scene = Scene::new();
+        let obj = scene
+            .add_mesh_file(Vec3::zero(), &format!("{corpus}/polygon.obj"))
+            .unwrap();
+        let ply = scene
+            .add_mesh_file(Vec3::zero(), &format!("{corpus}/quad.ply"))
+            .unwrap();
+        let (obj, ply) = (scene.object(obj), scene.object(ply));
+        assert_eq!((obj.tstart, obj.tstop, ply.tstart, ply.tstop), (0, 1, 2, 3));
+        assert_eq!(scene.vbuffer[..4], scene.vbuffer[4..]);
+
+        // and from the binary format, read in place
+        let dir = std::env::temp_dir().join(format!("bih-rs-test-formats-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let polygon = load_mesh_file(&format!("{corpus}/polygon.obj")).unwrap();
+        let bmesh = dir.join("polygon.bmesh");
+        let bmesh = bmesh.to_str().unwrap();
+        wfront::bmesh::save(bmesh, &polygon).unwrap();
+        let binary = scene.add_mesh_file(Vec3::zero(), bmesh).unwrap();
+        assert_eq!(scene.object(binary).tstart, 4);
+        assert_eq!(scene.vbuffer[..4], scene.vbuffer[8..]);
+        assert_eq!(scene.uvbuffer[..2], scene.uvbuffer[4..]);
+
+        // files without triangles are errors, not empty objects
+        let ply = dir.join("empty.ply");
+        let header = "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\n\
+                      property float y\nproperty float z\nelement face 0\n\
+                      property list uchar int vertex_indices\nend_header\n0 0 0\n";
+        std::fs::write(&ply, header).unwrap();
+        let stl = dir.join("empty.stl");
+        std::fs::write(&stl, [0u8; 84]).unwrap();
+        for path in [ply, stl] {
+            let err = scene
+                .add_mesh_file(Vec3::zero(), path.to_str().unwrap())
+                .unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        }
+        assert_eq!(scene.objects.len(), 3);
+
+        // without an extension, binary STL starting with "solid" is told
+        // apart by its size, and unreadable files are errors too
+        let noext = dir.join("noext");
+        let mut bytes = b"solid binary".to_vec();
+        bytes.resize(84 + 50 * 40, 0);
+        bytes[80] = 40;
+        std::fs::write(&noext, &bytes).unwrap();
+        let noext = noext.to_str().unwrap();
+        assert_eq!(mesh_format(noext).unwrap(), MeshFormat::Stl);
+        assert!(wfront::stl::load(noext, false).is_ok());
+        std::fs::remove_file(noext).unwrap();
+        let err = scene.add_wavefront(Vec3::zero(), noext).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        let err = scene.add_wavefront_groups(Vec3::zero(), noext).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // An OBJ naming two materials, added after a scene material, renders
+    // with materials of its own.
+    #[test]
+    fn test_mesh_materials() {
+        let dir =
+            std::env::temp_dir().join(format!("bih-rs-test-materials-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("quad.obj");
+        let obj = "v -6 -6 0\nv 6 -6 0\nv 6 6 0\nv -6 6 0\n\
+                   usemtl a\nf 1 2 3\nusemtl b\nf 1 3 4\n";
+        std::fs::write(&path, obj).unwrap();
+
+        let mut scene = Scene::new();
+        scene
+            .materials
+            .push(crate::types::default_material(Vec3::unit_x()));
+        scene
+            .add_mesh_file(Vec3::zero(), path.to_str().unwrap())
+            .unwrap();
+        let plane = concat!(env!("CARGO_MANIFEST_DIR"), "/../plane.obj");
+        scene.add_mesh_file(Vec3::zero(), plane).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(scene.materials.len(), 3);
+        assert_eq!((scene.tbuffer[0].mat, scene.tbuffer[1].mat), (1, 2));
+        assert!(scene.tbuffer[2..].iter().all(|t| t.mat == 0));
+
+        let bih = compute_bih(&scene, 4);
+        let camera = crate::camera::new(8.0, 6.0, 5.0).set_position(Vec3::new(0.0, 0.0, -10.0));
+        crate::trace::render(2, &scene, &bih, &camera, 8, 6);
+    }
+
+    // Moves the upper group of a quad away, leaving the lower one in place.
+    #[test]
+    fn test_move_group() {
+        use crate::traverse::traverse;
+        use crate::types::new_ray;
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../wfront/corpus/groups.obj");
+        let mut scene = Scene::new();
+        let groups = scene.add_wavefront_groups(Vec3::zero(), path).unwrap();
+        let (top, bottom) = (groups[0], groups[1]);
+        scene.set_position(top, Vec3::new(5.0, 0.0, 0.0));
+        scene.set_position(bottom, Vec3::zero());
+        let bih = compute_bih(&scene, 4);
+        let hit = |x: f32, y: f32| {
+            let ray = new_ray(Vec3::new(x, y, -5.0), Vec3::unit_z());
+            traverse(&scene, &bih, 0, &ray, 0.0, f32::MAX).map(|h| (h.tri, h.t))
+        };
+        assert_eq!(hit(0.25, 0.75), Some((1, 5.0)));
+        assert_eq!(hit(5.75, 0.25), Some((0, 5.0)));
+        assert_eq!(hit(0.75, 0.25), None);
+        assert_eq!(scene.nbuffer[1], scene.nbuffer[0]);
+        let bottom = scene.object(bottom).clone();
+        assert!(object_vertices(&scene.tbuffer, &bottom)
+            .iter()
+            .all(|v| scene.vbuffer[*v] == scene.rest[*v]));
+    }
+
+    // Moves the second of two spheres from x = 10 to x = 20.
+    #[test]
+    fn test_move_object() {
+        use crate::traverse::traverse;
+        use crate::types::new_ray;
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        let mut scene = Scene::new();
+        scene.add_wavefront(Vec3::zero(), path).unwrap();
+        let second = scene
+            .add_wavefront(Vec3::new(10.0, 0.0, 0.0), path)
+            .unwrap();
+        let mut bih = compute_bih(&scene, 4);
+        let hit = |scene: &Scene, bih: &BihState, x: f32| {
+            let ray = new_ray(Vec3::new(x, 0.1, -20.0), Vec3::unit_z());
+            traverse(scene, bih, 0, &ray, 0.0, f32::MAX).map(|h| h.tri)
+        };
+        assert!(hit(&scene, &bih, 10.0).is_some());
+        assert!(hit(&scene, &bih, 20.0).is_none());
+
+        scene.set_position(second, Vec3::new(10.0, 0.0, 0.0));
+        assert!(scene.global.maxs.x > 20.0);
+        let obj = scene.object(second).clone();
+        assert!(object_vertices(&scene.tbuffer, &obj)
+            .iter()
+            .all(|v| scene.vbuffer[*v] == scene.rest[*v] + Vec3::new(10.0, 0.0, 0.0)));
+
+        // the refitted and the rebuilt trees agree
+        refit_bih(&scene, &mut bih);
+        assert!(bih.validate().is_ok());
+        let rebuilt = compute_bih(&scene, 4);
+        for x in [0.0, 1.0, 10.0, 19.0, 20.0, 21.0] {
+            assert_eq!(hit(&scene, &bih, x), hit(&scene, &rebuilt, x));
+        }
+        let tri = hit(&scene, &bih, 20.0).unwrap();
+        assert_eq!(scene.object_of_triangle(tri), Some(1));
+        assert!(hit(&scene, &bih, 10.0).is_none());
+
+        // rotating recomputes the normals
+        scene.set_orientation(second, Rotor3::from_rotation_xz(1.0));
+        let t = scene.tbuffer[obj.tstart];
+        let p0 = scene.vbuffer[t.t0 as usize];
+        let p1 = scene.vbuffer[t.t1 as usize];
+        let p2 = scene.vbuffer[t.t2 as usize];
+        let normal = (p1 - p0).cross(p2 - p0).normalized();
+        assert!((scene.nbuffer[obj.tstart] - normal).mag() < 1e-5);
+    }
+
+    // A sphere sweeping from x = 0 to x = 10 over the shutter, then
+    // swelling in place.
+    #[test]
+    fn test_motion() {
+        use crate::traverse::traverse;
+        use crate::types::{new_ray, Ray};
+        let sphere = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        let plane = concat!(env!("CARGO_MANIFEST_DIR"), "/../plane.obj");
+        let mut scene = Scene::new();
+        let a = scene.add_wavefront(Vec3::zero(), sphere).unwrap();
+        scene.set_motion(a, Vec3::new(10.0, 0.0, 0.0), Rotor3::identity());
+        let b = scene
+            .add_wavefront(Vec3::new(0.0, -5.0, 0.0), plane)
+            .unwrap();
+        assert!(scene.is_moving(0) && !scene.is_moving(scene.object(b).tstart));
+        assert!(scene.global.maxs.x > 12.0);
+        let hit = |scene: &Scene, x: f32, time: f32| {
+            let bih = compute_bih(scene, 4);
+            let ray = Ray {
+                time,
+                ..new_ray(Vec3::new(x, 0.1, -20.0), Vec3::unit_z())
+            };
+            traverse(scene, &bih, 0, &ray, 0.0, f32::MAX).map(|h| h.tri)
+        };
+        assert!(hit(&scene, 0.0, 0.0).is_some());
+        assert!(hit(&scene, 0.0, 1.0).is_none());
+        assert!(hit(&scene, 10.0, 0.0).is_none());
+        assert!(hit(&scene, 10.0, 1.0).is_some());
+        assert!(hit(&scene, 5.0, 0.5).is_some());
+        // the moving normals follow the triangles
+        let [p0, p1, p2] = scene.triangle_at(0, 0.5);
+        let start = scene.vbuffer[scene.tbuffer[0].t0 as usize];
+        assert!((p0 - start - Vec3::new(5.0, 0.0, 0.0)).mag() < 1e-5);
+        assert_eq!(
+            scene.normal_at(0, 0.5),
+            (p1 - p0).cross(p2 - p0).normalized()
+        );
+
+        // removing the plane keeps the motion of the sphere
+        scene.remove_object(b);
+        assert!(hit(&scene, 10.0, 1.0).is_some());
+
+        // moving the sphere stops it
+        scene.set_position(a, Vec3::zero());
+        assert!(!scene.is_moving(0));
+        assert!(hit(&scene, 10.0, 1.0).is_none());
+        assert!(scene.global.maxs.x < 3.0);
+
+        // a deforming sphere
+        let end: Vec<Vertex> = scene
+            .object_vertices(a)
+            .iter()
+            .map(|v| scene.vbuffer[*v] * 2.0)
+            .collect();
+        scene.set_vertex_motion(a, &end);
+        assert!(hit(&scene, 4.0, 0.0).is_none());
+        assert!(hit(&scene, 4.0, 1.0).is_some());
+        scene.clear_motion();
+        assert!(hit(&scene, 4.0, 1.0).is_none());
+        assert!(scene.global.maxs.x < 3.0);
+
+        // a sphere spinning half a turn in place keeps its radius, and
+        // stays within its boxes, in mid-shutter; along the chords it would
+        // shrink to a point
+        let center = Vec3::new(3.0, 0.0, 0.0);
+        scene.set_position(a, center);
+        let half = Rotor3::from_rotation_xz(std::f32::consts::PI);
+        scene.set_motion(a, center, half);
+        for tri in 0..scene.tbuffer.len() {
+            let start = scene.triangle_at(tri, 0.0);
+            for time in [0.25, 0.5, 0.75] {
+                let corners = scene.triangle_at(tri, time);
+                for (p, q) in corners.iter().zip(start) {
+                    assert!(((*p - center).mag() - (q - center).mag()).abs() < 1e-4);
+                    let b = &scene.bboxes[tri];
+                    assert!((0..3).all(|d| b.mins[d] - 1e-4 <= p[d] && p[d] <= b.maxs[d] + 1e-4));
+                }
+            }
+        }
+        assert!(hit(&scene, 3.0, 0.5).is_some());
+    }
+
+    #[test]
+    fn test_remove_and_replace() {
+        let sphere = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        let plane = concat!(env!("CARGO_MANIFEST_DIR"), "/../plane.obj");
+        let mut scene = Scene::new();
+        let a = scene.add_wavefront(Vec3::zero(), plane).unwrap();
+        let b = scene.add_wavefront(Vec3::zero(), sphere).unwrap();
+        let c = scene
+            .add_wavefront(Vec3::new(0.0, 5.0, 0.0), plane)
+            .unwrap();
+        scene.set_position(c, Vec3::new(1.0, 0.0, 0.0));
+        let triangles = |scene: &Scene, h: ObjectHandle| -> Vec<[Vec3; 3]> {
+            let obj = scene.object(h);
+            scene.tbuffer[obj.tstart..=obj.tstop]
+                .iter()
+                .map(|t| [t.t0, t.t1, t.t2].map(|v| scene.vbuffer[v as usize]))
+                .collect()
+        };
+        let before = triangles(&scene, c);
+
+        scene.remove_object(b);
+        assert_eq!(scene.handles(), vec![a, c]);
+        assert_eq!(scene.vbuffer.len(), 8);
+        assert_eq!(scene.tbuffer.len(), 4);
+        assert_eq!((scene.object(c).tstart, scene.object(c).tstop), (2, 3));
+        assert_eq!(triangles(&scene, c), before);
+        assert_eq!(scene.global.maxs.y, 5.0);
+        let bih = compute_bih(&scene, 4);
+        assert!(bih.validate().is_ok());
+
+        // the replacement keeps the transform of `a`
+        scene.set_position(a, Vec3::new(0.0, -3.0, 0.0));
+        scene.replace_mesh(a, load_mesh_file(sphere).unwrap());
+        let spheres = scene.object(a).tstop + 1;
+        assert!(spheres > 2);
+        assert_eq!(scene.tbuffer.len(), spheres + 2);
+        assert_eq!(scene.object(c).tstart, spheres);
+        assert_eq!(triangles(&scene, c), before);
+        assert_eq!(scene.vbuffer.len(), 4 + 162);
+        assert_eq!(scene.object_of_triangle(spheres as u32), Some(1));
+        let n = scene.nbuffer[0];
+        let [p0, p1, p2] = triangles(&scene, a)[0];
+        assert_eq!(n, (p1 - p0).cross(p2 - p0).normalized());
+        assert!(scene.global.mins.y < -5.0);
+        scene.set_position(a, Vec3::zero());
+        assert!(scene.global.mins.y > -3.0);
+
+        scene.set_material(c, 1);
+        let mats: Vec<u32> = scene.tbuffer.iter().map(|t| t.mat).collect();
+        assert!(mats[..spheres].iter().all(|m| *m == 0));
+        assert_eq!(mats[spheres..], [1, 1]);
+    }
+}
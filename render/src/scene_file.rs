@@ -0,0 +1,701 @@
+use crate::bih::BihState;
+use crate::camera::{self, Camera};
+use crate::graph::{MeshId, SceneGraph, Transform};
+use crate::procedural::{Pattern, Procedural, Space, MAX_OCTAVES};
+use crate::scene::{self, ObjectHandle, Scene};
+use crate::texture::{self, ImageTexture, Texture, Wrap};
+use crate::types::{self, Light, Material};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use ultraviolet::bivec::Bivec3;
+use ultraviolet::rotor::Rotor3;
+use ultraviolet::vec::Vec3;
+
+// Declarative scene description, in TOML or JSON (picked by extension).
+//
+//   ambient = [0.0, 0.0, 0.0]
+//
+//   [render]
+//   resolution = [800, 600]
+//   depth = 2
+//   output = "out.pfm"                 # optional
+//
+//   [bih]
+//   leaf_bound = 6
+//
+//   [camera]
+//   position = [0.0, 0.0, -10.0]
+//   rotation = { angle = 0.0, axis = [0.0, 1.0, 0.0] }
+//   screen = [8.0, 6.0]
+//   eyedist = 5.0
+//
+//   [[textures]]
+//   name = "bricks"
+//   file = "bricks.png"                # PPM, PNG or TGA
+//   wrap = "repeat"                    # optional, or "clamp", "mirror"
+//
+//   [[textures]]
+//   name = "tiles"
+//   pattern = "checker"                # or "grid", "noise", "marble", "wood"
+//   space = "world"                    # optional, or "uv", "object"
+//   scale = 1.0                        # optional
+//   colors = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0]]  # optional
+//   # width (grid), octaves (at most 24), turbulence (noise, marble, wood)
+//   # optional
+//
+//   [[materials]]
+//   name = "white"
+//   color = [1.0, 1.0, 1.0]            # diffuse, specular, shininess optional
+//   color_map = "bricks"               # optional, also specular_map, normal_map
+//
+//   [[meshes]]
+//   name = "left"                      # optional
+//   parent = "pedestal"                # optional, the name of another entry
+//   file = "buddha.wobj"               # optional
+//   translation = [3.5, 0.0, 0.0]
+//   rotation = { angle = 90.0, axis = [0.0, 1.0, 0.0] }
+//   scale = [1.0, 1.0, 1.0]
+//   material = "white"
+//
+//   [[lights]]
+//   position = [5.0, 5.0, -10.0]
+//   intensity = 5.0
+//   color = [1.0, 0.0, 0.0]
+//
+// The meshes form a scene graph (see `graph`): an entry's transform is
+// relative to its parent's, and entries without a file only group their
+// children. Angles are in degrees. Vertices are scaled, then rotated, then
+// translated. Meshes use the named material, or the first one; their own
+// materials are ignored. Without any material a white one is added. Image
+// textures are looked up at the texcoords of the meshes, patterns in their
+// `space`; meshes are at rest where the file places them, so "object" space
+// only differs from "world" once they are moved. Relative paths are relative
+// to the scene file, and a file used by several entries is loaded once.
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile {
+    #[serde(default)]
+    pub ambient: [f32; 3],
+    #[serde(default)]
+    pub render: RenderSettings,
+    #[serde(default)]
+    pub bih: BihOptions,
+    #[serde(default)]
+    pub camera: CameraDesc,
+    #[serde(default)]
+    pub textures: Vec<TextureDesc>,
+    #[serde(default)]
+    pub materials: Vec<MaterialDesc>,
+    #[serde(default)]
+    pub meshes: Vec<MeshDesc>,
+    #[serde(default)]
+    pub lights: Vec<LightDesc>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderSettings {
+    pub resolution: [u32; 2],
+    pub depth: usize,
+    pub output: Option<String>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            resolution: [800, 600],
+            depth: 2,
+            output: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BihOptions {
+    pub leaf_bound: u32,
+}
+
+impl Default for BihOptions {
+    fn default() -> Self {
+        BihOptions { leaf_bound: 6 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AxisAngle {
+    pub angle: f32, // degrees
+    pub axis: [f32; 3],
+}
+
+impl AxisAngle {
+    pub fn rotor(&self) -> Rotor3 {
+        let axis = Bivec3::from_normalized_axis(Vec3::from(self.axis).normalized());
+        Rotor3::from_angle_plane(self.angle.to_radians(), axis)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraDesc {
+    pub position: [f32; 3],
+    pub rotation: Option<AxisAngle>,
+    pub screen: [f32; 2],
+    pub eyedist: f32,
+}
+
+impl Default for CameraDesc {
+    fn default() -> Self {
+        CameraDesc {
+            position: [0.0, 0.0, -10.0],
+            rotation: None,
+            screen: [8.0, 6.0],
+            eyedist: 5.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureDesc {
+    pub name: String,
+    pub file: Option<String>,
+    #[serde(default)]
+    pub wrap: Wrap,
+    pub pattern: Option<PatternName>,
+    #[serde(default)]
+    pub space: Space,
+    #[serde(default = "unit")]
+    pub scale: f32,
+    pub colors: Option<[[f32; 3]; 2]>,
+    pub width: Option<f32>,
+    pub octaves: Option<u32>,
+    pub turbulence: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatternName {
+    Checker,
+    Grid,
+    Noise,
+    Marble,
+    Wood,
+}
+
+fn unit() -> f32 {
+    1.0
+}
+
+impl TextureDesc {
+    fn procedural(&self, pattern: PatternName) -> std::io::Result<Procedural> {
+        let octaves = self.octaves.unwrap_or(4);
+        if octaves > MAX_OCTAVES {
+            return Err(invalid(&format!(
+                "texture {:?}: at most {MAX_OCTAVES} octaves",
+                self.name
+            )));
+        }
+        let pattern = match pattern {
+            PatternName::Checker => Pattern::Checker,
+            PatternName::Grid => Pattern::Grid {
+                width: self.width.unwrap_or(0.05),
+            },
+            PatternName::Noise => Pattern::Noise { octaves },
+            PatternName::Marble => Pattern::Marble {
+                octaves,
+                turbulence: self.turbulence.unwrap_or(5.0),
+            },
+            PatternName::Wood => Pattern::Wood {
+                octaves,
+                turbulence: self.turbulence.unwrap_or(0.5),
+            },
+        };
+        let mut procedural = Procedural::new(pattern, self.space);
+        procedural.scale = self.scale;
+        if let Some([c0, c1]) = self.colors {
+            procedural.colors = [Vec3::from(c0), Vec3::from(c1)];
+        }
+        Ok(procedural)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDesc {
+    pub name: String,
+    pub color: [f32; 3],
+    pub diffuse: Option<f32>,
+    pub specular: Option<f32>,
+    pub shininess: Option<f32>,
+    pub color_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub normal_map: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeshDesc {
+    pub name: Option<String>,
+    pub parent: Option<String>,
+    pub file: Option<String>,
+    #[serde(default)]
+    pub translation: [f32; 3],
+    pub rotation: Option<AxisAngle>,
+    #[serde(default = "unit_scale")]
+    pub scale: [f32; 3],
+    pub material: Option<String>,
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LightDesc {
+    pub position: [f32; 3],
+    pub intensity: f32,
+    #[serde(default = "unit_scale")]
+    pub color: [f32; 3],
+}
+
+// A scene file, loaded.
+pub struct Loaded {
+    pub scene: Scene,
+    pub objects: Vec<ObjectHandle>, // one per entry of `meshes` with a file
+    pub camera: Camera,
+    pub render: RenderSettings,
+    pub bih: BihOptions,
+}
+
+impl Loaded {
+    pub fn compute_bih(&self) -> BihState {
+        scene::compute_bih(&self.scene, self.bih.leaf_bound)
+    }
+}
+
+fn invalid(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("scene: {what}"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    pub fn from_extension(fname: &str) -> Option<Format> {
+        let ext = Path::new(fname).extension()?.to_str()?;
+        match ext.to_ascii_lowercase().as_str() {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(text: &str, format: Format) -> std::io::Result<SceneFile> {
+    match format {
+        Format::Toml => toml::from_str(text).map_err(|e| invalid(&e.to_string())),
+        Format::Json => serde_json::from_str(text).map_err(|e| invalid(&e.to_string())),
+    }
+}
+
+impl SceneFile {
+    pub fn camera(&self) -> Camera {
+        let [width, height] = self.camera.screen;
+        let camera = camera::new(width, height, self.camera.eyedist)
+            .set_position(Vec3::from(self.camera.position));
+        match &self.camera.rotation {
+            Some(rotation) => camera.set_orientation(rotation.rotor()),
+            None => camera,
+        }
+    }
+
+    fn materials(&self) -> std::io::Result<Vec<Material>> {
+        if self.materials.is_empty() {
+            return Ok(vec![types::default_material(Vec3::one())]);
+        }
+        self.materials
+            .iter()
+            .map(|m| {
+                let default = types::default_material(Vec3::from(m.color));
+                Ok(Material {
+                    m_diffuse: m.diffuse.unwrap_or(default.m_diffuse),
+                    m_specular: m.specular.unwrap_or(default.m_specular),
+                    m_shininess: m.shininess.unwrap_or(default.m_shininess),
+                    color_map: self.texture_id(&m.color_map)?,
+                    specular_map: self.texture_id(&m.specular_map)?,
+                    normal_map: self.texture_id(&m.normal_map)?,
+                    ..default
+                })
+            })
+            .collect()
+    }
+
+    fn texture_id(&self, name: &Option<String>) -> std::io::Result<Option<u32>> {
+        match name {
+            None => Ok(None),
+            Some(name) => self
+                .textures
+                .iter()
+                .position(|t| &t.name == name)
+                .map(|i| Some(i as u32))
+                .ok_or_else(|| invalid(&format!("unknown texture {name:?}"))),
+        }
+    }
+
+    // The textures, loading their files relative to `base`.
+    pub fn textures(&self, base: &Path) -> std::io::Result<Vec<Texture>> {
+        self.textures
+            .iter()
+            .map(|desc| match (&desc.file, desc.pattern) {
+                (Some(file), None) => {
+                    let path = base.join(file);
+                    let fname = path.to_string_lossy();
+                    let image = texture::load(&fname)?;
+                    Ok(Texture::Image(ImageTexture::new(image, desc.wrap)))
+                }
+                (None, Some(pattern)) => Ok(Texture::Procedural(desc.procedural(pattern)?)),
+                _ => Err(invalid(&format!(
+                    "texture {:?} needs either a file or a pattern",
+                    desc.name
+                ))),
+            })
+            .collect()
+    }
+
+    fn material_id(&self, name: &Option<String>) -> std::io::Result<u32> {
+        match name {
+            None => Ok(0),
+            Some(name) => self
+                .materials
+                .iter()
+                .position(|m| &m.name == name)
+                .map(|i| i as u32)
+                .ok_or_else(|| invalid(&format!("unknown material {name:?}"))),
+        }
+    }
+
+    // The graph of `meshes`, loading their files relative to `base`.
+    pub fn graph(&self, base: &Path) -> std::io::Result<SceneGraph> {
+        let mut graph = SceneGraph::new();
+        let mut files: HashMap<&str, MeshId> = HashMap::new();
+        for desc in self.meshes.iter() {
+            let mesh = match desc.file.as_deref() {
+                None => None,
+                Some(file) => Some(match files.get(file) {
+                    Some(id) => *id,
+                    None => {
+                        let path = base.join(file);
+                        let fname = path.to_string_lossy();
+                        let mesh = scene::load_mesh_file(&fname)?;
+                        if mesh.triangles.is_empty() {
+                            return Err(scene::no_triangles(&fname));
+                        }
+                        let id = graph.add_mesh(mesh);
+                        files.insert(file, id);
+                        id
+                    }
+                }),
+            };
+            let transform = Transform {
+                translation: Vec3::from(desc.translation),
+                rotation: desc.rotation.map_or(Rotor3::identity(), |r| r.rotor()),
+                scale: Vec3::from(desc.scale),
+            };
+            let name = desc.name.as_deref().unwrap_or("");
+            let id = graph.add_node(None, name, transform, mesh);
+            graph.node_mut(id).material = Some(self.material_id(&desc.material)?);
+        }
+        // parents may come after their children
+        for (id, desc) in self.meshes.iter().enumerate() {
+            if let Some(parent) = &desc.parent {
+                let p = graph
+                    .find(parent)
+                    .ok_or_else(|| invalid(&format!("unknown parent {parent:?}")))?;
+                if !graph.set_parent(id, Some(p)) {
+                    return Err(invalid(&format!("{parent:?} is its own ancestor")));
+                }
+            }
+        }
+        Ok(graph)
+    }
+
+    // Builds the scene, resolving relative paths against `base`.
+    pub fn build(&self, base: &Path) -> std::io::Result<Loaded> {
+        let mut scene = Scene::new();
+        scene.ambient = Vec3::from(self.ambient);
+        scene.materials = self.materials()?;
+        scene.textures = self.textures(base)?;
+        scene.lights = self
+            .lights
+            .iter()
+            .map(|l| Light {
+                position: Vec3::from(l.position),
+                intensity: l.intensity,
+                color: Vec3::from(l.color),
+            })
+            .collect();
+
+        let graph = self.graph(base)?;
+        let objects = graph
+            .build(&mut scene)
+            .into_iter()
+            .map(|(_, obj)| obj)
+            .collect();
+
+        let mut render = self.render.clone();
+        render.output = render
+            .output
+            .map(|o| base.join(o).to_string_lossy().into_owned());
+        Ok(Loaded {
+            scene,
+            objects,
+            camera: self.camera(),
+            render,
+            bih: self.bih.clone(),
+        })
+    }
+}
+
+// Loads a `.toml` or `.json` scene file.
+pub fn load(fname: &str) -> std::io::Result<Loaded> {
+    let format = Format::from_extension(fname)
+        .ok_or_else(|| invalid(&format!("{fname}: expected a .toml or .json file")))?;
+    let desc = parse(&std::fs::read_to_string(fname)?, format)?;
+    let base: PathBuf = Path::new(fname)
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+    desc.build(&base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        ambient = [0.1, 0.1, 0.1]
+
+        [render]
+        resolution = [40, 30]
+
+        [bih]
+        leaf_bound = 4
+
+        [camera]
+        position = [0.0, 0.0, -10.0]
+        rotation = { angle = 180.0, axis = [0.0, 1.0, 0.0] }
+
+        [[materials]]
+        name = "white"
+        color = [1.0, 1.0, 1.0]
+
+        [[materials]]
+        name = "red"
+        color = [1.0, 0.0, 0.0]
+        specular = 0.5
+
+        [[meshes]]
+        file = "sphere2.obj"
+        translation = [3.0, 0.0, 0.0]
+        scale = [2.0, 2.0, 2.0]
+        material = "red"
+
+        [[meshes]]
+        file = "plane.obj"
+        parent = "ground"
+        rotation = { angle = 90.0, axis = [1.0, 0.0, 0.0] }
+
+        [[meshes]]
+        name = "ground"
+        translation = [0.0, -5.0, 0.0]
+
+        [[lights]]
+        position = [5.0, 5.0, -10.0]
+        intensity = 5.0
+    "#;
+
+    const JSON: &str = r#"{
+        "ambient": [0.1, 0.1, 0.1],
+        "render": { "resolution": [40, 30] },
+        "bih": { "leaf_bound": 4 },
+        "camera": {
+            "position": [0.0, 0.0, -10.0],
+            "rotation": { "angle": 180.0, "axis": [0.0, 1.0, 0.0] }
+        },
+        "materials": [
+            { "name": "white", "color": [1.0, 1.0, 1.0] },
+            { "name": "red", "color": [1.0, 0.0, 0.0], "specular": 0.5 }
+        ],
+        "meshes": [
+            { "file": "sphere2.obj", "translation": [3.0, 0.0, 0.0],
+              "scale": [2.0, 2.0, 2.0], "material": "red" },
+            { "file": "plane.obj", "parent": "ground",
+              "rotation": { "angle": 90.0, "axis": [1.0, 0.0, 0.0] } },
+            { "name": "ground", "translation": [0.0, -5.0, 0.0] }
+        ],
+        "lights": [ { "position": [5.0, 5.0, -10.0], "intensity": 5.0 } ]
+    }"#;
+
+    fn base() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("..")
+    }
+
+    #[test]
+    fn test_toml_and_json() {
+        let desc = parse(TOML, Format::Toml).unwrap();
+        assert_eq!(parse(JSON, Format::Json).unwrap(), desc);
+        assert_eq!(desc.render.depth, 2);
+        assert_eq!(desc.meshes[1].scale, [1.0, 1.0, 1.0]);
+        assert_eq!(desc.lights[0].color, [1.0, 1.0, 1.0]);
+
+        let loaded = desc.build(&base()).unwrap();
+        let scene = &loaded.scene;
+        assert_eq!(loaded.objects.len(), 2);
+        assert_eq!(scene.materials.len(), 2);
+        assert_eq!(scene.materials[1].m_specular, 0.5);
+        assert_eq!(scene.ambient, Vec3::new(0.1, 0.1, 0.1));
+        let sphere = scene.object(loaded.objects[0]);
+        assert!(scene.tbuffer[sphere.tstart..=sphere.tstop]
+            .iter()
+            .all(|t| t.mat == 1));
+        assert_eq!(scene.tbuffer[scene.object(loaded.objects[1]).tstart].mat, 0);
+
+        // the plane (y = 0, |x|, |z| <= 10) is rotated into z = 0 and then
+        // moved down with its parent; the sphere is scaled about the origin
+        // before being translated
+        let plane =
+            scene.vbuffer[scene.tbuffer[scene.object(loaded.objects[1]).tstart].t0 as usize];
+        assert!(plane.z.abs() < 1e-5);
+        assert!(((plane.y + 5.0).abs() - 10.0).abs() < 1e-4);
+        let xs = scene.tbuffer[sphere.tstart..=sphere.tstop]
+            .iter()
+            .flat_map(|t| [t.t0, t.t1, t.t2])
+            .map(|v| scene.vbuffer[v as usize].x);
+        let max = xs.fold(f32::MIN, f32::max);
+        assert!((max - (3.0 + 2.0 * 2.5456)).abs() < 1e-3);
+
+        // rotated by half a turn, the camera looks down -z
+        let rays: Vec<_> = loaded.camera.iter_rays(1, 1).collect();
+        assert!(rays[0].2.normal.z < 0.0);
+        assert_eq!(loaded.compute_bih().index.len(), scene.tbuffer.len());
+    }
+
+    #[test]
+    fn test_errors() {
+        let unknown = TOML.replace("material = \"red\"", "material = \"blue\"");
+        let desc = parse(&unknown, Format::Toml).unwrap();
+        assert!(desc.build(&base()).is_err());
+        assert!(parse("[camera]\nfov = 45", Format::Toml).is_err());
+        let cycle = TOML.replace(
+            "name = \"ground\"",
+            "name = \"ground\"\nparent = \"ground\"",
+        );
+        let desc = parse(&cycle, Format::Toml).unwrap();
+        assert!(desc.build(&base()).is_err());
+        assert!(load("scene.yaml").is_err());
+
+        // the example scene parses
+        let example = std::fs::read_to_string(base().join("scene.toml")).unwrap();
+        let example = parse(&example, Format::Toml).unwrap();
+        assert_eq!(example.meshes.len(), 3);
+        assert_eq!(example.textures[0].pattern, Some(PatternName::Checker));
+    }
+
+    #[test]
+    fn test_textures() {
+        // a single red texel
+        let dir = std::env::temp_dir().join(format!("bih-rs-test-textures-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("red.ppm"), b"P3 1 1 255 255 0 0").unwrap();
+        let cube = base().join("cube.obj");
+        let text = format!(
+            r#"
+            [camera]
+            position = [0.0, 0.0, -10.0]
+
+            [[textures]]
+            name = "red"
+            file = "red.ppm"
+            wrap = "clamp"
+
+            [[materials]]
+            name = "white"
+            color = [1.0, 1.0, 1.0]
+            color_map = "red"
+
+            [[meshes]]
+            file = {cube:?}
+            scale = [4.0, 4.0, 4.0]
+
+            [[lights]]
+            position = [0.0, 0.0, -10.0]
+            intensity = 5.0
+            "#
+        );
+        let desc = parse(&text, Format::Toml).unwrap();
+        assert_eq!(desc.textures[0].wrap, Wrap::Clamp);
+        let loaded = desc.build(&dir).unwrap();
+        assert_eq!(loaded.scene.textures.len(), 1);
+        assert_eq!(loaded.scene.materials[0].color_map, Some(0));
+        assert!(loaded.scene.uvbuffer.iter().any(|uv| uv[1] != uv[0]));
+
+        // the cube takes the color of its texture
+        let fb = crate::trace::render(
+            1,
+            &loaded.scene,
+            &loaded.compute_bih(),
+            &loaded.camera,
+            4,
+            3,
+        );
+        let center = fb.rgb(2, 1);
+        assert!(center.x > 0.0 && center.y == 0.0 && center.z == 0.0);
+
+        let unknown = text.replace("color_map = \"red\"", "normal_map = \"bumps\"");
+        assert!(parse(&unknown, Format::Toml).unwrap().build(&dir).is_err());
+
+        // a pattern instead of the file: blue and red cells along x
+        let pattern = text.replace(
+            "file = \"red.ppm\"",
+            "pattern = \"checker\"\nspace = \"object\"\ncolors = [[1.0, 0.0, 0.0], [0.0, 0.0, 1.0]]",
+        );
+        let desc = parse(&pattern.replace("wrap = \"clamp\"", ""), Format::Toml).unwrap();
+        let loaded = desc.build(&dir).unwrap();
+        let Texture::Procedural(checker) = &loaded.scene.textures[0] else {
+            panic!("expected a procedural texture");
+        };
+        assert_eq!(
+            (checker.pattern, checker.space),
+            (Pattern::Checker, Space::Object)
+        );
+        let fb = crate::trace::render(
+            1,
+            &loaded.scene,
+            &loaded.compute_bih(),
+            &loaded.camera,
+            4,
+            3,
+        );
+        let (left, right) = (fb.rgb(1, 1), fb.rgb(2, 1));
+        assert!(left.x == 0.0 && left.z > 0.0);
+        assert!(right.x > 0.0 && right.z == 0.0);
+
+        // either a file or a pattern
+        let both = pattern.replace(
+            "pattern = \"checker\"",
+            "pattern = \"checker\"\nfile = \"red.ppm\"",
+        );
+        assert!(parse(&both, Format::Toml).unwrap().build(&dir).is_err());
+        let noisy = pattern.replace("pattern = \"checker\"", "pattern = \"noise\"\noctaves = 40");
+        assert!(parse(&noisy, Format::Toml).unwrap().build(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
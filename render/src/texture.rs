@@ -0,0 +1,433 @@
+use crate::procedural::Procedural;
+use std::io::{BufRead, Seek};
+use std::path::Path;
+use ultraviolet::vec::{Vec2, Vec3};
+use ultraviolet::Lerp;
+
+// Textures, looked up at a hit: images, at the texcoords with bilinear
+// filtering, or procedural patterns (see `procedural`).
+//
+// Texcoords follow the Wavefront convention: (0, 0) is the bottom left
+// corner of the image and (1, 1) the top right one. Texels are used as
+// stored, scaled to [0, 1], without decoding sRGB.
+
+fn invalid(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("texture: {what}"))
+}
+
+// How texcoords outside [0, 1] map to the image.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    Clamp,  // to the edge texels
+    Mirror, // repeat, flipping every other copy
+}
+
+impl Wrap {
+    fn texel(self, i: i64, n: u32) -> u32 {
+        let n = n as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(n),
+            Wrap::Clamp => i.clamp(0, n - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+        };
+        i as u32
+    }
+}
+
+// RGB pixels, row by row from the top.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec3>,
+}
+
+impl Image {
+    pub fn pixel(&self, x: u32, y: u32) -> Vec3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+// Where a texture is looked up: the texcoords of the hit, and its position
+// in world space and on the object at rest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lookup {
+    pub uv: Vec2,
+    pub world: Vec3,
+    pub object: Vec3,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Texture {
+    Image(ImageTexture),
+    Procedural(Procedural),
+}
+
+impl Texture {
+    pub fn sample(&self, at: &Lookup) -> Vec3 {
+        match self {
+            Texture::Image(texture) => texture.sample(at.uv),
+            Texture::Procedural(texture) => texture.sample(at),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImageTexture {
+    pub image: Image,
+    pub wrap: Wrap,
+}
+
+impl ImageTexture {
+    pub fn new(image: Image, wrap: Wrap) -> Self {
+        assert!(image.width > 0 && image.height > 0, "texture: empty image");
+        ImageTexture { image, wrap }
+    }
+
+    // Bilinear interpolation of the four texels around `uv`, texel centers
+    // lying at half-integer coordinates.
+    pub fn sample(&self, uv: Vec2) -> Vec3 {
+        let (w, h) = (self.image.width, self.image.height);
+        let x = uv.x * w as f32 - 0.5;
+        let y = (1.0 - uv.y) * h as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let texel = |dx: i64, dy: i64| {
+            let tx = self.wrap.texel(x0 + dx, w);
+            let ty = self.wrap.texel(y0 + dy, h);
+            self.image.pixel(tx, ty)
+        };
+        let top = texel(0, 0).lerp(texel(1, 0), fx);
+        let bottom = texel(0, 1).lerp(texel(1, 1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+// Loads a PPM (or PGM), PNG or TGA image, by extension.
+pub fn load(path: &str) -> std::io::Result<Image> {
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("ppm" | "pgm" | "pnm") => read_ppm(&std::fs::read(path)?),
+        Some("tga") => read_tga(&std::fs::read(path)?),
+        Some("png") => read_png(std::io::BufReader::new(std::fs::File::open(path)?)),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("{path}: can only load .ppm, .pgm, .png or .tga textures"),
+        )),
+    }
+}
+
+// Netpbm: ASCII (P2, P3) or binary (P5, P6) graymaps and pixmaps, with up
+// to 16 bits per sample.
+pub fn read_ppm(bytes: &[u8]) -> std::io::Result<Image> {
+    let mut pos = 0;
+    // next whitespace-separated header word, skipping comments
+    let mut word = || -> std::io::Result<&[u8]> {
+        loop {
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if bytes.get(pos) != Some(&b'#') {
+                break;
+            }
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start < pos {
+            Ok(&bytes[start..pos])
+        } else {
+            Err(invalid("truncated ppm"))
+        }
+    };
+    let (channels, binary) = match word()? {
+        b"P2" => (1, false),
+        b"P3" => (3, false),
+        b"P5" => (1, true),
+        b"P6" => (3, true),
+        _ => return Err(invalid("not a ppm or pgm file")),
+    };
+    let mut number = || -> std::io::Result<u32> {
+        std::str::from_utf8(word()?)
+            .ok()
+            .and_then(|w| w.parse().ok())
+            .ok_or_else(|| invalid("bad ppm header"))
+    };
+    let (width, height, max) = (number()?, number()?, number()?);
+    if width == 0 || height == 0 {
+        return Err(invalid("empty ppm"));
+    }
+    if !(1..=65535).contains(&max) {
+        return Err(invalid("bad ppm maximum value"));
+    }
+    let count = (width as usize * height as usize)
+        .checked_mul(channels)
+        .ok_or_else(|| invalid("ppm too large"))?;
+    let samples: Vec<u32> = if binary {
+        // a single whitespace byte separates the header from the samples
+        let start = pos + 1;
+        let size = if max > 255 { 2 } else { 1 };
+        let end = count
+            .checked_mul(size)
+            .and_then(|n| n.checked_add(start))
+            .ok_or_else(|| invalid("ppm too large"))?;
+        let body = bytes
+            .get(start..end)
+            .ok_or_else(|| invalid("truncated ppm"))?;
+        match size {
+            1 => body.iter().map(|b| *b as u32).collect(),
+            _ => body
+                .chunks(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as u32)
+                .collect(),
+        }
+    } else {
+        (0..count).map(|_| number()).collect::<Result<_, _>>()?
+    };
+    let scale = 1.0 / max as f32;
+    let pixels = samples
+        .chunks(channels)
+        .map(|c| match c {
+            [g] => Vec3::broadcast(*g as f32 * scale),
+            _ => Vec3::new(c[0] as f32, c[1] as f32, c[2] as f32) * scale,
+        })
+        .collect();
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// Truevision TGA: uncompressed or run-length encoded, true-color (24 or 32
+// bits) or grayscale (8 bits). Color-mapped images are not supported.
+pub fn read_tga(bytes: &[u8]) -> std::io::Result<Image> {
+    let header = bytes.get(..18).ok_or_else(|| invalid("truncated tga"))?;
+    let id_length = header[0] as usize;
+    let (rle, gray) = match header[2] {
+        2 => (false, false),
+        3 => (false, true),
+        10 => (true, false),
+        11 => (true, true),
+        _ => return Err(invalid("unsupported tga image type")),
+    };
+    let width = u16::from_le_bytes([header[12], header[13]]) as u32;
+    let height = u16::from_le_bytes([header[14], header[15]]) as u32;
+    if width == 0 || height == 0 {
+        return Err(invalid("empty tga"));
+    }
+    let size = match (gray, header[16]) {
+        (true, 8) => 1,
+        (false, 24) => 3,
+        (false, 32) => 4,
+        _ => return Err(invalid("unsupported tga pixel depth")),
+    };
+    let top_down = header[17] & 0x20 != 0;
+    let color_map =
+        u16::from_le_bytes([header[5], header[6]]) as usize * header[7].div_ceil(8) as usize;
+    let mut data = bytes
+        .get(18 + id_length + color_map..)
+        .ok_or_else(|| invalid("truncated tga"))?;
+
+    let count = width as usize * height as usize;
+    let len = count * size;
+    // the data must be able to hold the image before room is made for it:
+    // raw pixels as they are, or packets of a header byte and one pixel
+    // repeated up to 128 times
+    let least = if rle {
+        count.div_ceil(128) * (1 + size)
+    } else {
+        len
+    };
+    if data.len() < least {
+        return Err(invalid("truncated tga"));
+    }
+    let mut raw = Vec::with_capacity(len);
+    if !rle {
+        raw.extend_from_slice(data.get(..len).ok_or_else(|| invalid("truncated tga"))?);
+    }
+    // packets: a header byte, then one pixel repeated or several raw pixels
+    while raw.len() < len {
+        let (&h, rest) = data.split_first().ok_or_else(|| invalid("truncated tga"))?;
+        let (count, run) = ((h as usize & 0x7f) + 1, h & 0x80 != 0);
+        let (packet, rest) = rest
+            .split_at_checked(if run { size } else { count * size })
+            .ok_or_else(|| invalid("truncated tga"))?;
+        data = rest;
+        if run {
+            (0..count).for_each(|_| raw.extend_from_slice(packet));
+        } else {
+            raw.extend_from_slice(packet);
+        }
+    }
+    raw.truncate(len);
+
+    let texel = |p: &[u8]| match p {
+        [g] => Vec3::broadcast(*g as f32 / 255.0),
+        // stored as BGR(A)
+        _ => Vec3::new(p[2] as f32, p[1] as f32, p[0] as f32) / 255.0,
+    };
+    let mut pixels: Vec<Vec3> = raw.chunks(size).map(texel).collect();
+    if !top_down {
+        let rows: Vec<&[Vec3]> = pixels.chunks(width.max(1) as usize).rev().collect();
+        pixels = rows.concat();
+    }
+    Ok(Image {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// PNG, through the `png` crate. Alpha is dropped.
+pub fn read_png<R: BufRead + Seek>(reader: R) -> std::io::Result<Image> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    if reader.info().width == 0 || reader.info().height == 0 {
+        return Err(invalid("empty png"));
+    }
+    let size = reader
+        .output_buffer_size()
+        .ok_or_else(|| invalid("png too large"))?;
+    let mut buf = vec![0; size];
+    let info = reader.next_frame(&mut buf)?;
+    let channels = info.color_type.samples();
+    let pixels = buf[..info.buffer_size()]
+        .chunks(info.line_size)
+        .flat_map(|row| row.chunks(channels).take(info.width as usize))
+        .map(|p| match p {
+            [g] | [g, _] => Vec3::broadcast(*g as f32 / 255.0),
+            _ => Vec3::new(p[0] as f32, p[1] as f32, p[2] as f32) / 255.0,
+        })
+        .collect();
+    Ok(Image {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2: red, green on top; blue, white below
+    fn quad() -> Image {
+        Image {
+            width: 2,
+            height: 2,
+            pixels: vec![Vec3::unit_x(), Vec3::unit_y(), Vec3::unit_z(), Vec3::one()],
+        }
+    }
+
+    fn approx(a: Vec3, b: Vec3) -> bool {
+        (a - b).mag() < 1e-5
+    }
+
+    #[test]
+    fn test_sample() {
+        let texture = ImageTexture::new(quad(), Wrap::Repeat);
+        // texel centers
+        assert!(approx(
+            texture.sample(Vec2::new(0.25, 0.75)),
+            Vec3::unit_x()
+        ));
+        assert!(approx(texture.sample(Vec2::new(0.75, 0.25)), Vec3::one()));
+        // halfway between red and green
+        let top = texture.sample(Vec2::new(0.5, 0.75));
+        assert!(approx(top, Vec3::new(0.5, 0.5, 0.0)));
+        // the center averages all four
+        let center = texture.sample(Vec2::new(0.5, 0.5));
+        assert!(approx(center, Vec3::new(0.5, 0.5, 0.5)));
+
+        // at the left edge, repeating blends in the right column
+        let edge = Vec2::new(0.0, 0.75);
+        assert!(approx(texture.sample(edge), Vec3::new(0.5, 0.5, 0.0)));
+        let clamped = ImageTexture::new(quad(), Wrap::Clamp);
+        assert!(approx(clamped.sample(edge), Vec3::unit_x()));
+        assert!(approx(clamped.sample(Vec2::new(-3.0, 9.0)), Vec3::unit_x()));
+        let mirrored = ImageTexture::new(quad(), Wrap::Mirror);
+        assert!(approx(mirrored.sample(edge), Vec3::unit_x()));
+        assert!(approx(
+            mirrored.sample(Vec2::new(1.25, 0.75)),
+            Vec3::unit_y()
+        ));
+        assert!(approx(
+            texture.sample(Vec2::new(1.25, 0.75)),
+            Vec3::unit_x()
+        ));
+    }
+
+    #[test]
+    fn test_formats() {
+        let ascii = b"P3\n# comment\n2 2\n255\n255 0 0  0 255 0\n0 0 255 255 255 255\n";
+        assert_eq!(read_ppm(ascii).unwrap(), quad());
+        let mut binary = b"P6 2 2 65535\n".to_vec();
+        for p in quad().pixels {
+            for c in [p.x, p.y, p.z] {
+                binary.extend_from_slice(&(c as u16 * 65535).to_be_bytes());
+            }
+        }
+        assert_eq!(read_ppm(&binary).unwrap(), quad());
+        assert!(read_ppm(b"P6 2 2 255\n\0\0\0").is_err());
+        assert!(read_ppm(b"P4 2 2\n").is_err());
+        // empty images are rejected rather than turned into textures
+        assert!(read_ppm(b"P6 0 0 255\n").is_err());
+        // sizes overflowing the sample count are rejected too
+        assert!(read_ppm(b"P5 4294967295 4294967295 65535\n\0\0").is_err());
+
+        // bottom-up rows, BGR, with a run of two blue pixels then a raw
+        // packet for the top row
+        let mut tga = vec![0, 0, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 2, 0, 24, 0];
+        tga.extend_from_slice(&[0x81, 255, 0, 0, 0x01, 0, 0, 255, 0, 255, 0]);
+        let image = read_tga(&tga).unwrap();
+        assert_eq!(image.pixel(0, 0), Vec3::unit_x());
+        assert_eq!(image.pixel(1, 0), Vec3::unit_y());
+        assert_eq!(image.pixel(0, 1), Vec3::unit_z());
+        assert_eq!(image.pixel(1, 1), Vec3::unit_z());
+        assert!(read_tga(&tga[..20]).is_err());
+        let mut empty = tga[..18].to_vec();
+        empty[12] = 0;
+        assert!(read_tga(&empty).is_err());
+        // the largest image, with data for a single packet
+        let mut large = tga.clone();
+        large[12..16].copy_from_slice(&[0xff; 4]);
+        assert!(read_tga(&large).is_err());
+        large[2] = 2;
+        assert!(read_tga(&large).is_err());
+
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, 2, 2);
+            encoder.set_color(png::ColorType::Rgba);
+            let mut writer = encoder.write_header().unwrap();
+            let data: Vec<u8> = quad()
+                .pixels
+                .iter()
+                .flat_map(|p| [p.x, p.y, p.z, 0.5].map(|c| (c * 255.0) as u8))
+                .collect();
+            writer.write_image_data(&data).unwrap();
+        }
+        assert_eq!(read_png(std::io::Cursor::new(png)).unwrap(), quad());
+        assert!(load("texture.bmp").is_err());
+    }
+}
@@ -1,4 +1,6 @@
 use crate::bih::BihState;
+use crate::camera::Camera;
+use crate::framebuffer::{self, Framebuffer};
 use crate::scene::{Scene, Triangle};
 use crate::traverse::traverse;
 use crate::types::{Hit, Light, Material, Ray};
@@ -8,7 +10,7 @@ type Rgb = Vec3;
 
 const BLACK: Rgb = Vec3::new(0.0, 0.0, 0.0);
 
-fn shadow_ray(normal: Vec3, hit_pos: Vec3, light_pos: Vec3) -> (Ray, f32) {
+fn shadow_ray(normal: Vec3, hit_pos: Vec3, light_pos: Vec3, time: f32) -> (Ray, f32) {
     let shifted_hit_pos = hit_pos + 0.1 * normal;
     let vec = light_pos - shifted_hit_pos;
     let length = vec.mag();
@@ -20,11 +22,67 @@ fn shadow_ray(normal: Vec3, hit_pos: Vec3, light_pos: Vec3) -> (Ray, f32) {
             origin: shifted_hit_pos,
             normal,
             inormal,
+            time,
         },
         ilength,
     )
 }
 
+// Material channels at a hit, textures applied.
+struct Surface {
+    color: Rgb,
+    reflection: f32, // scales the reflected light
+    normal: Vec3,
+}
+
+fn surface(scene: &Scene, material: &Material, hit: &Hit, time: f32) -> Surface {
+    let tri = hit.tri as usize;
+    let normal = scene.normal_at(tri, time);
+    let maps = [
+        material.color_map,
+        material.specular_map,
+        material.normal_map,
+    ];
+    if maps.iter().all(Option::is_none) {
+        return Surface {
+            color: material.m_color,
+            reflection: 1.0,
+            normal,
+        };
+    }
+    let at = scene.lookup(hit, time);
+    let sample = |map: Option<u32>| map.map(|i| scene.textures[i as usize].sample(&at));
+    Surface {
+        color: sample(material.color_map).map_or(material.m_color, |c| material.m_color * c),
+        reflection: sample(material.specular_map).map_or(1.0, |s| s.x),
+        normal: match sample(material.normal_map) {
+            Some(texel) => normal_mapped(scene, tri, time, normal, texel),
+            None => normal,
+        },
+    }
+}
+
+// `normal` perturbed by a normal map texel, in the tangent frame that the
+// texcoords of the triangle define.
+fn normal_mapped(scene: &Scene, tri: usize, time: f32, normal: Vec3, texel: Vec3) -> Vec3 {
+    let [p0, p1, p2] = scene.triangle_at(tri, time);
+    let [uv0, uv1, uv2] = scene.uvbuffer[tri];
+    let (e1, e2) = (p1 - p0, p2 - p0);
+    let (d1, d2) = (uv1 - uv0, uv2 - uv0);
+    let det = d1.x * d2.y - d2.x * d1.y;
+    if det.abs() < 1e-12 {
+        // no texcoords, or degenerate ones
+        return normal;
+    }
+    let tangent = (e1 * d2.y - e2 * d1.y) / det;
+    let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+    let tangent = (tangent - normal * normal.dot(tangent)).normalized();
+    let side = normal.cross(tangent);
+    let bitangent = side * side.dot(bitangent).signum();
+    let m = texel * 2.0 - Vec3::one();
+    (tangent * m.x + bitangent * m.y + normal * m.z).normalized()
+}
+
 pub fn raytrace(maxdepth: usize, scene: &Scene, bih: &BihState, ray: &Ray) -> Vec3 {
     if maxdepth <= 0 {
         // background shader ray
@@ -36,7 +94,6 @@ pub fn raytrace(maxdepth: usize, scene: &Scene, bih: &BihState, ray: &Ray) -> Ve
 
     let hit = traverse(scene, bih, 0, ray, tmin, tmax);
 
-    let nbuffer: &[Vec3] = &scene.nbuffer;
     let tbuffer: &[Triangle] = &scene.tbuffer;
     let materials: &[Material] = &scene.materials;
 
@@ -45,16 +102,12 @@ pub fn raytrace(maxdepth: usize, scene: &Scene, bih: &BihState, ray: &Ray) -> Ve
             // Should be background shader ray
             BLACK
         }
-        Some(Hit {
-            t,
-            u: _,
-            v: _,
-            dot,
-            tri,
-        }) => {
+        Some(hit) => {
+            let Hit { t, dot, tri, .. } = hit;
             // compute reflection and shadow rays
-            let tri_norm = nbuffer[tri as usize];
             let material = &materials[tbuffer[tri as usize].mat as usize];
+            let surface = surface(scene, material, &hit, ray.time);
+            let tri_norm = surface.normal;
             let hitpoint = ray.origin + t * ray.normal;
             let dotprod = -2.0 * dot;
             let refl_dir = ray.normal + dotprod * tri_norm;
@@ -63,12 +116,13 @@ pub fn raytrace(maxdepth: usize, scene: &Scene, bih: &BihState, ray: &Ray) -> Ve
                 origin: hitpoint,
                 normal: refl_dir,
                 inormal: reciprocal,
+                time: ray.time,
             };
             let lights = &scene.lights;
 
             let mut illumination = scene.ambient;
             for l in lights.into_iter() {
-                let (sray, ilength) = shadow_ray(tri_norm, hitpoint, l.position);
+                let (sray, ilength) = shadow_ray(tri_norm, hitpoint, l.position, ray.time);
                 // let ilength = 1.0;
                 // let shifted_hitpoint = hitpoint; // - 1. * tri_norm;
                 // let normal = (ray.origin - shifted_hitpoint).normalized();
@@ -87,14 +141,56 @@ pub fn raytrace(maxdepth: usize, scene: &Scene, bih: &BihState, ray: &Ray) -> Ve
                         // let light_color = Vec3::new(0.5, 0.5, 0.5);
                         let light_color =
                             (tri_norm.dot(sray.normal).abs() * l.intensity * ilength) * l.color;
-                        let result_color = material.m_color * (material.m_diffuse * light_color);
+                        let result_color = surface.color * (material.m_diffuse * light_color);
                         illumination += result_color;
                     }
                 }
             }
             let reflected_color = raytrace(maxdepth - 1, scene, bih, &rray);
-            let result_color = material.m_color * (material.m_diffuse * reflected_color);
+            let reflectance = material.m_diffuse * surface.reflection;
+            let result_color = surface.color * (reflectance * reflected_color);
             result_color + illumination
         }
     }
 }
+
+// Renders the scene into a linear RGB framebuffer, without any clamping or
+// tone mapping.
+pub fn render(
+    maxdepth: usize,
+    scene: &Scene,
+    bih: &BihState,
+    camera: &Camera,
+    xres: u32,
+    yres: u32,
+) -> Framebuffer {
+    let mut fb = framebuffer::new(xres, yres, 3);
+    camera.iter_rays(xres, yres).for_each(|(x, y, ray)| {
+        fb.set_rgb(x, y, raytrace(maxdepth, scene, bih, &ray));
+    });
+    fb
+}
+
+// Like `render`, averaging for each pixel `samples` rays at times spread
+// evenly over the shutter interval, which blurs moving objects.
+pub fn render_motion_blur(
+    maxdepth: usize,
+    scene: &Scene,
+    bih: &BihState,
+    camera: &Camera,
+    xres: u32,
+    yres: u32,
+    samples: u32,
+) -> Framebuffer {
+    assert!(samples > 0, "render_motion_blur: no samples");
+    let mut fb = framebuffer::new(xres, yres, 3);
+    camera.iter_rays(xres, yres).for_each(|(x, y, ray)| {
+        let mut color = BLACK;
+        for s in 0..samples {
+            let time = (s as f32 + 0.5) / samples as f32;
+            color += raytrace(maxdepth, scene, bih, &Ray { time, ..ray });
+        }
+        fb.set_rgb(x, y, color / samples as f32);
+    });
+    fb
+}
@@ -1,8 +1,39 @@
 use crate::bih::{BihState, Node};
 use crate::scene::Scene;
-use crate::triaccel::{triaccel_intersect, TriAccel};
+use crate::triaccel::{precompute, triaccel_intersect, TriAccel};
 use crate::types::{Hit, Ray};
 
+// Hooks through which the traversal reports the work it does. The `()`
+// instance does nothing and compiles away in the uninstrumented path.
+pub trait Counters {
+    fn visit_node(&mut self);
+    fn test_triangle(&mut self);
+}
+
+impl Counters for () {
+    #[inline(always)]
+    fn visit_node(&mut self) {}
+
+    #[inline(always)]
+    fn test_triangle(&mut self) {}
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TraversalStats {
+    pub nodes: u32,     // BIH nodes visited, leaves included
+    pub triangles: u32, // ray/triangle intersection tests
+}
+
+impl Counters for TraversalStats {
+    fn visit_node(&mut self) {
+        self.nodes += 1;
+    }
+
+    fn test_triangle(&mut self) {
+        self.triangles += 1;
+    }
+}
+
 pub fn intersect_ray(
     scene: &Scene,
     index: &[u32],
@@ -11,6 +42,20 @@ pub fn intersect_ray(
     tmax: f32,
     tri_start: &u32,
     tri_end: &u32,
+) -> Option<Hit> {
+    intersect_ray_counted(scene, index, ray, tmin, tmax, tri_start, tri_end, &mut ())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn intersect_ray_counted<C: Counters>(
+    scene: &Scene,
+    index: &[u32],
+    ray: &Ray,
+    tmin: f32,
+    tmax: f32,
+    tri_start: &u32,
+    tri_end: &u32,
+    counters: &mut C,
 ) -> Option<Hit> {
     let mut min_hit = Hit {
         t: f32::INFINITY,
@@ -23,6 +68,7 @@ pub fn intersect_ray(
     let abuffer: &[TriAccel] = &scene.triaccels;
 
     for tri in *tri_start..=*tri_end {
+        counters.test_triangle();
         let i = index[tri as usize];
         let triaccel = &abuffer[i as usize];
         let mut hit = Hit {
@@ -33,7 +79,14 @@ pub fn intersect_ray(
             tri: i,
         };
 
-        if triaccel_intersect(&triaccel, ray, tmin, tmax, &mut hit) && hit.t < min_hit.t {
+        // moving triangles are intersected where they are at the ray's time
+        let found = if scene.is_moving(i as usize) {
+            let [p0, p1, p2] = scene.triangle_at(i as usize, ray.time);
+            triaccel_intersect(&precompute(p0, p1, p2), ray, tmin, tmax, &mut hit)
+        } else {
+            triaccel_intersect(triaccel, ray, tmin, tmax, &mut hit)
+        };
+        if found && hit.t < min_hit.t {
             min_hit = hit;
         }
     }
@@ -52,14 +105,40 @@ pub fn traverse(
     ray: &Ray,
     tmin: f32,
     tmax: f32,
+) -> Option<Hit> {
+    traverse_counted(scene, bih, node_index, ray, tmin, tmax, &mut ())
+}
+
+// Same as `traverse`, also returning the amount of work performed.
+pub fn traverse_stats(
+    scene: &Scene,
+    bih: &BihState,
+    ray: &Ray,
+    tmin: f32,
+    tmax: f32,
+) -> (Option<Hit>, TraversalStats) {
+    let mut stats = TraversalStats::default();
+    let hit = traverse_counted(scene, bih, 0, ray, tmin, tmax, &mut stats);
+    (hit, stats)
+}
+
+pub fn traverse_counted<C: Counters>(
+    scene: &Scene,
+    bih: &BihState,
+    node_index: u32,
+    ray: &Ray,
+    tmin: f32,
+    tmax: f32,
+    counters: &mut C,
 ) -> Option<Hit> {
     let node = &bih.nodes[node_index as usize];
     if tmin >= tmax {
         return None;
     };
+    counters.visit_node();
     match node {
         Node::Leaf { start, stop } => {
-            intersect_ray(scene, &bih.index, ray, tmin, tmax, start, stop)
+            intersect_ray_counted(scene, &bih.index, ray, tmin, tmax, start, stop, counters)
         }
         Node::Node {
             axis,
@@ -79,7 +158,8 @@ pub fn traverse(
                     // ray intersects left subspace
                     let far_clip = f32::min((leftclip - ray.origin[dim]) * ray.inormal[dim], tmax);
                     // explore left
-                    let left_hit = traverse(scene, bih, *left, ray, tmin, far_clip);
+                    let left_hit =
+                        traverse_counted(scene, bih, *left, ray, tmin, far_clip, counters);
 
                     if leftclip <= rightclip {
                         // boxes do not overlap - we explore the right if
@@ -91,7 +171,9 @@ pub fn traverse(
                                         (rightclip - ray.origin[dim]) * ray.inormal[dim],
                                         tmin,
                                     );
-                                    traverse(scene, bih, right, ray, near_clip, tmax)
+                                    traverse_counted(
+                                        scene, bih, right, ray, near_clip, tmax, counters,
+                                    )
                                 } else {
                                     None
                                 }
@@ -102,7 +184,8 @@ pub fn traverse(
                         // boxes do overlap - we have to explore both boxes and pick the nearest hit
                         let near_clip =
                             f32::max((rightclip - ray.origin[dim]) * ray.inormal[dim], tmin);
-                        let right_hit = traverse(scene, bih, right, ray, near_clip, tmax);
+                        let right_hit =
+                            traverse_counted(scene, bih, right, ray, near_clip, tmax, counters);
                         match (left_hit, right_hit) {
                             (None, None) => None,
                             (None, x) | (x, None) => x,
@@ -122,7 +205,7 @@ pub fn traverse(
                     // ray does not intersect left subspace but intersects right one
                     let near_clip =
                         f32::max((rightclip - ray.origin[dim]) * ray.inormal[dim], tmin);
-                    traverse(scene, bih, right, ray, near_clip, tmax)
+                    traverse_counted(scene, bih, right, ray, near_clip, tmax, counters)
                 } else {
                     None
                 }
@@ -132,7 +215,8 @@ pub fn traverse(
                     // ray intersects right subspace
                     let far_clip = f32::min((rightclip - ray.origin[dim]) * ray.inormal[dim], tmax);
                     // explore right
-                    let right_hit = traverse(scene, bih, right, ray, tmin, far_clip);
+                    let right_hit =
+                        traverse_counted(scene, bih, right, ray, tmin, far_clip, counters);
 
                     if leftclip < rightclip {
                         // boxes do not overlap - we explore the right if
@@ -144,7 +228,9 @@ pub fn traverse(
                                         (leftclip - ray.origin[dim]) * ray.inormal[dim],
                                         tmin,
                                     );
-                                    traverse(scene, bih, *left, ray, near_clip, tmax)
+                                    traverse_counted(
+                                        scene, bih, *left, ray, near_clip, tmax, counters,
+                                    )
                                 } else {
                                     None
                                 }
@@ -155,7 +241,8 @@ pub fn traverse(
                         // boxes do overlap - we have to explore both boxes and pick the nearest hit
                         let near_clip =
                             f32::max((leftclip - ray.origin[dim]) * ray.inormal[dim], tmin);
-                        let left_hit = traverse(scene, bih, *left, ray, near_clip, tmax);
+                        let left_hit =
+                            traverse_counted(scene, bih, *left, ray, near_clip, tmax, counters);
 
                         match (right_hit, left_hit) {
                             (None, None) => None,
@@ -175,7 +262,7 @@ pub fn traverse(
                 } else if ray_stop <= *leftclip {
                     // ray does not intersect left subspace but intersects right one
                     let near_clip = f32::max((leftclip - ray.origin[dim]) * ray.inormal[dim], tmin);
-                    traverse(scene, bih, *left, ray, near_clip, tmax)
+                    traverse_counted(scene, bih, *left, ray, near_clip, tmax, counters)
                 } else {
                     None
                 }
@@ -183,3 +270,34 @@ pub fn traverse(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::new_ray;
+    use ultraviolet::vec::Vec3;
+
+    #[test]
+    fn test_traverse_stats() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        let mut scene = Scene::new();
+        scene.add_wavefront(Vec3::zero(), path).unwrap();
+        let bih = crate::scene::compute_bih(&scene, 4);
+        let origin = Vec3::new(0.0, 0.0, -10.0);
+
+        let ray = new_ray(origin, Vec3::unit_z());
+        let (hit, stats) = traverse_stats(&scene, &bih, &ray, 1.0, f32::MAX);
+        assert!(hit.is_some());
+        assert_eq!(
+            hit.map(|h| h.tri),
+            traverse(&scene, &bih, 0, &ray, 1.0, f32::MAX).map(|h| h.tri)
+        );
+        assert!(stats.nodes > 0 && stats.triangles > 0);
+
+        // a ray passing by the sphere still visits the root
+        let ray = new_ray(origin, Vec3::unit_y());
+        let (hit, stats) = traverse_stats(&scene, &bih, &ray, 1.0, f32::MAX);
+        assert!(hit.is_none());
+        assert!(stats.nodes > 0);
+    }
+}
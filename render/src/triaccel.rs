@@ -131,6 +131,31 @@ pub fn triaccel_intersect(tri: &TriAccel, ray: &Ray, tmin: f32, tmax: f32, hit:
     }
 
     hit.t = f;
+    hit.u = mu;
+    hit.v = lambda;
     hit.dot = nd;
     return true;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::moller_trumbore::test_intersection;
+    use crate::types::{new_hit, new_ray};
+
+    #[test]
+    fn test_barycentrics_match_moller_trumbore() {
+        let p0 = Vec3::new(-1.0, -0.5, 2.0);
+        let p1 = Vec3::new(1.5, 0.0, 2.5);
+        let p2 = Vec3::new(0.0, 1.5, 1.5);
+        let tri = precompute(p0, p1, p2);
+        let ray = new_ray(Vec3::zero(), Vec3::new(0.1, 0.2, 1.0).normalized());
+        let mut expected = new_hit();
+        let mut hit = new_hit();
+        assert!(test_intersection(&ray, p0, p1, p2, &mut expected));
+        assert!(triaccel_intersect(&tri, &ray, 0.0, f32::MAX, &mut hit));
+        assert!((hit.t - expected.t).abs() < 1e-5);
+        assert!((hit.u - expected.u).abs() < 1e-5);
+        assert!((hit.v - expected.v).abs() < 1e-5);
+    }
+}
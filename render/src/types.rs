@@ -22,6 +22,7 @@ pub struct Ray {
     pub origin: Vec3,
     pub normal: Vec3,
     pub inormal: Vec3,
+    pub time: f32, // within the shutter interval, from 0 (open) to 1 (close)
 }
 
 pub struct Ray8 {
@@ -39,8 +40,16 @@ pub struct Light {
 pub struct Material {
     pub m_color: Vec3,
     pub m_diffuse: f32, // Proportion of the light emitted by the actual light sources that is reflected by the surface
+    // `m_specular` and `m_shininess` are not read by the tracer, which
+    // scales reflections by `m_diffuse` as it always has. They carry the
+    // materials of scene files, glTF (metallic, roughness) and MTL exports
+    // (`Ks`, `Ns`) through.
     pub m_specular: f32,
     pub m_shininess: f32,
+    // Indices in `Scene::textures`, sampled at the hit's texcoords
+    pub color_map: Option<u32>,    // multiplies `m_color`
+    pub specular_map: Option<u32>, // its red channel scales the reflected light
+    pub normal_map: Option<u32>,   // tangent-space normals
 }
 
 pub fn new_hit() -> Hit {
@@ -59,6 +68,7 @@ pub fn new_ray(origin: Vec3, normal: Vec3) -> Ray {
         origin,
         normal,
         inormal,
+        time: 0.0,
     }
 }
 
@@ -77,5 +87,8 @@ pub fn default_material(color: Vec3) -> Material {
         m_diffuse: 1.0,
         m_specular: 1.0,
         m_shininess: 1.0,
+        color_map: None,
+        specular_map: None,
+        normal_map: None,
     }
 }
@@ -1,11 +1,10 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use raylib::prelude::*;
 use render::types::Ray;
-use render::{camera, scene, trace, types};
+use render::{camera, procedural, scene, texture, trace, types};
 
 use std::str::FromStr;
 use ultraviolet::Vec3;
-use wfront::loader::{load, Mesh};
 
 fn parse_r(arg: &str) -> Result<WindowResolution, std::io::Error> {
     let mut cs = arg.split('x');
@@ -37,64 +36,112 @@ impl std::fmt::Display for WindowResolution {
     }
 }
 
+#[derive(Copy, Clone, ValueEnum)]
+pub enum HeatmapMetric {
+    Nodes,
+    Triangles,
+}
+
+impl From<HeatmapMetric> for render::heatmap::Metric {
+    fn from(metric: HeatmapMetric) -> Self {
+        match metric {
+            HeatmapMetric::Nodes => render::heatmap::Metric::Nodes,
+            HeatmapMetric::Triangles => render::heatmap::Metric::Triangles,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Convert an OBJ, PLY or STL file to the binary mesh format
+    Convert { input: String, output: String },
+    /// Render a scene description (.toml or .json), to the file given by
+    /// --output or the scene's `render.output`, or else in a window
+    Render {
+        scene: String,
+        /// Write the linear (unclamped) radiance to a .pfm or .tiff file
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Render a turntable of a scene description: its meshes make one turn
+    /// about the vertical axis through the scene's center, one frame per
+    /// image file
+    Turntable {
+        scene: String,
+        /// A .pfm or .tiff path; frame numbers are inserted before the
+        /// extension
+        #[arg(short, long)]
+        output: String,
+        /// Frames per turn, at least 4
+        #[arg(long, default_value_t = 120, value_parser = clap::value_parser!(u32).range(4..))]
+        frames: u32,
+        /// Rebuild the BIH between frames instead of refitting it
+        #[arg(long)]
+        rebuild: bool,
+        /// Fraction of a frame the shutter stays open, for motion blur
+        #[arg(long, default_value_t = 0.0)]
+        shutter: f32,
+        /// Rays per pixel spread over the shutter interval
+        #[arg(long, default_value_t = 8)]
+        samples: u32,
+    },
+}
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[arg(short, long, default_value_t = DEFAULT_WINDOW_RESOLUTION, value_parser = parse_r)]
     pub resolution: WindowResolution,
-    pub filename: String,
+    /// Write the linear (unclamped) radiance to a .pfm or .tiff file
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// Write per-pixel auxiliary passes (depth, normal, ids, barycentrics)
+    /// to PREFIX_<pass>.pfm
+    #[arg(long, value_name = "PREFIX")]
+    pub aov: Option<String>,
+    /// Display the BIH traversal cost of primary rays instead of shading
+    #[arg(long, value_enum)]
+    pub heatmap: Option<HeatmapMetric>,
+    /// Print statistics about the BIH after construction
+    #[arg(long)]
+    pub stats: bool,
+    /// Load the scene geometry and BIH from FILE, rebuilding and rewriting
+    /// it when missing or stale
+    #[arg(long, value_name = "FILE")]
+    pub cache: Option<String>,
+    #[arg(required = true)]
+    pub filename: Option<String>,
 }
 
-pub fn main() {
-    let args = Args::parse();
+fn convert(input: &str, output: &str) {
+    let mesh = scene::load_mesh_file(input).unwrap();
+    wfront::bmesh::save(output, &mesh).unwrap();
+    println!(
+        "Wrote {output}: vertices = {}; triangles = {}",
+        mesh.vertices.len(),
+        mesh.triangles.len()
+    );
+}
 
-    let xres = args.resolution.xres;
-    let yres = args.resolution.yres;
+// Traces the scene into a window, once per frame, until it is closed.
+fn display(
+    scene: &scene::Scene,
+    bih: &render::bih::BihState,
+    camera: &camera::Camera,
+    (xres, yres): (u32, u32),
+    maxdepth: usize,
+    heatmap: Option<render::framebuffer::Framebuffer>,
+) {
+    use std::time::Instant;
 
     let (mut rl, thrd) = raylib::init()
         .size(xres as i32, yres as i32)
         .title("BIH")
         .build();
 
-    let mut scene = render::scene::Scene::new();
-    let _obj = scene.add_wavefront(Vec3::new(3.5, 0.0, 0.0), &args.filename);
-    let _obj = scene.add_wavefront(Vec3::new(-3.5, 0.0, 0.0), &args.filename);
-    let _obj = scene.add_wavefront(Vec3::new(0.0, -5.0, 0.0), "plane.obj");
-
-    // need proper BIH construction
-    // obj.set_position(Vec3::new(1., 1., 0.));
-    // scene.refresh_triaccel(&obj);
-
-    scene
-        .materials
-        .push(types::default_material(Vec3::new(1.0, 1.0, 1.0)));
-
-    scene.lights.push(types::Light {
-        position: Vec3::new(5.0, 5.0, -10.0),
-        intensity: 5.0,
-        color: Vec3::new(1.0, 0.0, 0.0),
-    });
-
-    scene.lights.push(types::Light {
-        position: Vec3::new(-5.0, 5.0, -10.0),
-        intensity: 5.0,
-        color: Vec3::new(0.0, 0.0, 1.0),
-    });
-
-    use std::time::Instant;
-
-    let now = Instant::now();
-
-    let bih = scene::compute_bih(&scene, 6);
-
-    let elapsed = now.elapsed().as_nanos();
-
-    println!("Construction time: {elapsed} ns");
-
-    let camera = camera::new(8., 6., 5.)
-        .set_position(Vec3::new(0.0, 0.0, -10.))
-        .set_orientation_angle_axis(0.0, Vec3::new(0.0, 1.0, 0.0));
-
     let mut iter = 0;
 
     'running: while !rl.window_should_close() {
@@ -107,8 +154,11 @@ pub fn main() {
 
         let now = Instant::now();
 
-        camera.iter_rays(800, 600).for_each(|(x, y, ray)| {
-            let color_vec = render::trace::raytrace(2, &scene, &bih, &ray);
+        camera.iter_rays(xres, yres).for_each(|(x, y, ray)| {
+            let color_vec = match &heatmap {
+                Some(fb) => fb.rgb(x, y),
+                None => render::trace::raytrace(maxdepth, scene, bih, &ray),
+            };
             let r = (color_vec.x.clamp(0., 1.) * 255.) as u8;
             let g = (color_vec.y.clamp(0., 1.) * 255.) as u8;
             let b = (color_vec.z.clamp(0., 1.) * 255.) as u8;
@@ -122,3 +172,247 @@ pub fn main() {
         println!("Rendering time: {elapsed} ms");
     }
 }
+
+// Loads a scene file, reporting the size of the scene.
+fn load_scene_file(fname: &str) -> render::scene_file::Loaded {
+    println!("Loading {fname}");
+    let loaded = render::scene_file::load(fname).unwrap();
+    println!(
+        "objects = {}; vertices = {}; triangles = {}",
+        loaded.scene.objects.len(),
+        loaded.scene.vbuffer.len(),
+        loaded.scene.tbuffer.len()
+    );
+    loaded
+}
+
+fn render_scene_file(fname: &str, output: Option<&str>) {
+    use std::time::Instant;
+
+    let loaded = load_scene_file(fname);
+
+    let now = Instant::now();
+    let bih = loaded.compute_bih();
+    let elapsed = now.elapsed().as_nanos();
+    println!("Construction time: {elapsed} ns");
+
+    let [xres, yres] = loaded.render.resolution;
+    let depth = loaded.render.depth;
+    match output.or(loaded.render.output.as_deref()) {
+        Some(output) => {
+            let fb = trace::render(depth, &loaded.scene, &bih, &loaded.camera, xres, yres);
+            render::framebuffer::save(&fb, output).unwrap();
+            println!("Wrote {output}");
+        }
+        None => display(&loaded.scene, &bih, &loaded.camera, (xres, yres), depth, None),
+    }
+}
+
+fn turntable(
+    fname: &str,
+    output: &str,
+    frames: u32,
+    rebuild: bool,
+    shutter: f32,
+    samples: u32,
+) {
+    use render::animation::{self, Animation, BatchOptions, BihUpdate};
+    use std::time::Instant;
+
+    let mut loaded = load_scene_file(fname);
+    let global = &loaded.scene.global;
+    let center = (global.mins + global.maxs) * 0.5;
+    let track = animation::turntable(center, Vec3::unit_y(), frames as f32, frames);
+    let animation = Animation {
+        objects: loaded.objects.iter().map(|h| (*h, track.clone())).collect(),
+        camera: None,
+    };
+    let options = BatchOptions {
+        frames,
+        fps: 1.0,
+        update: if rebuild {
+            BihUpdate::Rebuild
+        } else {
+            BihUpdate::Refit
+        },
+        leaf_bound: loaded.bih.leaf_bound,
+        maxdepth: loaded.render.depth,
+        resolution: (loaded.render.resolution[0], loaded.render.resolution[1]),
+        shutter,
+        samples,
+    };
+
+    let now = Instant::now();
+    let mut bih = loaded.compute_bih();
+    let written = animation::render_frames(
+        &mut loaded.scene,
+        &mut bih,
+        &loaded.camera,
+        &animation,
+        &options,
+        output,
+    )
+    .unwrap();
+    let elapsed = now.elapsed().as_millis();
+    println!("Wrote {} frames in {elapsed} ms", written.len());
+}
+
+pub fn main() {
+    let args = Args::parse();
+
+    match &args.command {
+        Some(Command::Convert { input, output }) => {
+            convert(input, output);
+            return;
+        }
+        Some(Command::Render { scene, output }) => {
+            render_scene_file(scene, output.as_deref());
+            return;
+        }
+        Some(Command::Turntable {
+            scene,
+            output,
+            frames,
+            rebuild,
+            shutter,
+            samples,
+        }) => {
+            turntable(scene, output, *frames, *rebuild, *shutter, *samples);
+            return;
+        }
+        None => (),
+    }
+    let filename = args.filename.as_deref().unwrap();
+
+    let xres = args.resolution.xres;
+    let yres = args.resolution.yres;
+
+    use std::time::Instant;
+
+    const LEAF_BOUND: u32 = 6;
+
+    let meshes = [
+        (Vec3::new(3.5, 0.0, 0.0), filename),
+        (Vec3::new(-3.5, 0.0, 0.0), filename),
+        (Vec3::new(0.0, -5.0, 0.0), "plane.obj"),
+    ];
+
+    // The cache is keyed by the meshes' contents, their placement and the
+    // BIH parameters.
+    let cache_key = || {
+        let mut hasher = render::cache::Hasher::default();
+        for (shift, fname) in meshes {
+            hasher.write_file(fname).unwrap();
+            hasher.write_f32(shift.x);
+            hasher.write_f32(shift.y);
+            hasher.write_f32(shift.z);
+        }
+        hasher.write_u32(LEAF_BOUND);
+        hasher.finish()
+    };
+
+    let cached = args.cache.as_ref().and_then(|path| {
+        let now = Instant::now();
+        let cached = render::cache::load(path, cache_key()).unwrap();
+        if cached.is_some() {
+            let elapsed = now.elapsed().as_nanos();
+            println!("Loaded {path} in {elapsed} ns");
+        }
+        cached
+    });
+
+    let (mut scene, bih, handles) = match cached {
+        Some((scene, bih)) => {
+            let handles = scene.handles();
+            (scene, bih, handles)
+        }
+        None => {
+            // the first material is white, for meshes without their own
+            let mut scene = render::scene::Scene::new();
+            scene
+                .materials
+                .push(types::default_material(Vec3::new(1.0, 1.0, 1.0)));
+            let handles: Vec<scene::ObjectHandle> = meshes
+                .iter()
+                .map(|(shift, fname)| {
+                    println!("Loading {fname}");
+                    let handle = scene.add_mesh_file(*shift, fname).unwrap();
+                    let obj = scene.object(handle);
+                    println!(
+                        "vertices = {}; triangles = {}",
+                        scene.object_vertices(handle).len(),
+                        obj.tstop - obj.tstart + 1
+                    );
+                    handle
+                })
+                .collect();
+
+            let now = Instant::now();
+
+            let bih = scene::compute_bih(&scene, LEAF_BOUND);
+
+            let elapsed = now.elapsed().as_nanos();
+
+            println!("Construction time: {elapsed} ns");
+
+            if let Some(path) = &args.cache {
+                render::cache::save(path, cache_key(), &scene, &bih).unwrap();
+                println!("Wrote {path}");
+            }
+            (scene, bih, handles)
+        }
+    };
+
+    // a checker on the ground plane, the last mesh
+    let mut checker =
+        procedural::Procedural::new(procedural::Pattern::Checker, procedural::Space::World);
+    checker.scale = 0.5;
+    checker.colors = [Vec3::new(0.9, 0.9, 0.9), Vec3::new(0.3, 0.3, 0.3)];
+    let texture = scene.textures.len() as u32;
+    scene.textures.push(texture::Texture::Procedural(checker));
+    let material = scene.materials.len() as u32;
+    scene.materials.push(types::Material {
+        color_map: Some(texture),
+        ..types::default_material(Vec3::new(1.0, 1.0, 1.0))
+    });
+    scene.set_material(handles[meshes.len() - 1], material);
+
+    scene.lights.push(types::Light {
+        position: Vec3::new(5.0, 5.0, -10.0),
+        intensity: 5.0,
+        color: Vec3::new(1.0, 0.0, 0.0),
+    });
+
+    scene.lights.push(types::Light {
+        position: Vec3::new(-5.0, 5.0, -10.0),
+        intensity: 5.0,
+        color: Vec3::new(0.0, 0.0, 1.0),
+    });
+
+    if args.stats {
+        print!("{}", bih.stats());
+    }
+
+    let camera = camera::new(8., 6., 5.)
+        .set_position(Vec3::new(0.0, 0.0, -10.))
+        .set_orientation_angle_axis(0.0, Vec3::new(0.0, 1.0, 0.0));
+
+    if let Some(output) = &args.output {
+        let fb = trace::render(2, &scene, &bih, &camera, xres, yres);
+        render::framebuffer::save(&fb, output).unwrap();
+        println!("Wrote {output}");
+    }
+
+    if let Some(prefix) = &args.aov {
+        let aovs = render::aov::render(&scene, &bih, &camera, xres, yres);
+        aovs.save(prefix, "pfm").unwrap();
+        println!("Wrote {prefix}_*.pfm");
+    }
+
+    let heatmap = args.heatmap.map(|metric| {
+        let counts = render::heatmap::render(&scene, &bih, &camera, xres, yres);
+        render::heatmap::colorize(&counts, metric.into())
+    });
+
+    display(&scene, &bih, &camera, (xres, yres), 2, heatmap);
+}
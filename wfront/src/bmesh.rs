@@ -0,0 +1,324 @@
+use crate::loader::{Mesh, Triangle, NO_TEXCOORDS, V3};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+
+// Binary mesh format, meant to be read without parsing.
+//
+// Layout (little endian, every section 4-byte aligned):
+//   magic "BMSH" | version: u32
+//   counts: vertices, normals, texcoords, triangles, materials,
+//           uv triangles (u32 each)
+//   vertices:  3 x f32 each
+//   normals:   3 x f32 each
+//   texcoords: 3 x f32 each
+//   triangles: 3 x u32 each (1-based, as in Wavefront files)
+//   materials: u32 per triangle, or none at all
+//   uv triangles: 3 x u32 per triangle, or none at all
+//
+// Version 1 files have neither the uv triangle count nor its section.
+//
+// The sections have the in-memory layout of the corresponding `Mesh`
+// vectors on little-endian hosts, so `view` can borrow them in place.
+// Colors and groups are not stored.
+
+pub const MAGIC: &[u8; 4] = b"BMSH";
+pub const VERSION: u32 = 2;
+const HEADER_SIZE: usize = 4 + 4 + 6 * 4;
+
+fn invalid(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("bmesh: {what}"))
+}
+
+// Borrowed view of a binary mesh.
+#[derive(Debug)]
+pub struct MeshView<'a> {
+    pub vertices: &'a [V3],
+    pub normals: &'a [V3],
+    pub texcoords: &'a [V3],
+    pub triangles: &'a [Triangle],
+    pub materials: &'a [u32],
+    pub uv_triangles: &'a [Triangle],
+}
+
+impl<'a> From<&'a Mesh> for MeshView<'a> {
+    fn from(mesh: &'a Mesh) -> Self {
+        MeshView {
+            vertices: &mesh.vertices,
+            normals: &mesh.normals,
+            texcoords: &mesh.texcoords,
+            triangles: &mesh.triangles,
+            materials: &mesh.materials,
+            uv_triangles: &mesh.uv_triangles,
+        }
+    }
+}
+
+impl MeshView<'_> {
+    // 1-based indices in `texcoords` of the corners of triangle `i`: from
+    // `uv_triangles`, or else from `triangles` when there is one texcoord
+    // per vertex (as in PLY files).
+    pub fn texcoord_indices(&self, i: usize) -> Option<Triangle> {
+        match self.uv_triangles.get(i) {
+            Some(&NO_TEXCOORDS) => None,
+            Some(t) => Some(*t),
+            None if !self.texcoords.is_empty() && self.texcoords.len() == self.vertices.len() => {
+                Some(self.triangles[i])
+            }
+            None => None,
+        }
+    }
+
+    pub fn to_mesh(&self) -> Mesh {
+        Mesh {
+            vertices: self.vertices.to_vec(),
+            normals: self.normals.to_vec(),
+            texcoords: self.texcoords.to_vec(),
+            colors: Vec::new(),
+            triangles: self.triangles.to_vec(),
+            uv_triangles: self.uv_triangles.to_vec(),
+            materials: self.materials.to_vec(),
+            material_names: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+}
+
+fn write_u32s<W: Write>(out: &mut W, xs: impl Iterator<Item = u32>) -> std::io::Result<()> {
+    for x in xs {
+        out.write_all(&x.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn write_v3s<W: Write>(out: &mut W, vs: &[V3]) -> std::io::Result<()> {
+    write_u32s(
+        out,
+        vs.iter()
+            .flat_map(|V3(x, y, z)| [x.to_bits(), y.to_bits(), z.to_bits()]),
+    )
+}
+
+fn write_triangles<W: Write>(out: &mut W, ts: &[Triangle]) -> std::io::Result<()> {
+    write_u32s(
+        out,
+        ts.iter().flat_map(|Triangle(t0, t1, t2)| [*t0, *t1, *t2]),
+    )
+}
+
+pub fn write<W: Write>(out: &mut W, mesh: &Mesh) -> std::io::Result<()> {
+    if !mesh.materials.is_empty() && mesh.materials.len() != mesh.triangles.len() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "bmesh: one material id per triangle expected",
+        ));
+    }
+    if !mesh.uv_triangles.is_empty() && mesh.uv_triangles.len() != mesh.triangles.len() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "bmesh: one uv triangle per triangle expected",
+        ));
+    }
+    out.write_all(MAGIC)?;
+    write_u32s(
+        out,
+        [
+            VERSION,
+            mesh.vertices.len() as u32,
+            mesh.normals.len() as u32,
+            mesh.texcoords.len() as u32,
+            mesh.triangles.len() as u32,
+            mesh.materials.len() as u32,
+            mesh.uv_triangles.len() as u32,
+        ]
+        .into_iter(),
+    )?;
+    write_v3s(out, &mesh.vertices)?;
+    write_v3s(out, &mesh.normals)?;
+    write_v3s(out, &mesh.texcoords)?;
+    write_triangles(out, &mesh.triangles)?;
+    write_u32s(out, mesh.materials.iter().copied())?;
+    write_triangles(out, &mesh.uv_triangles)
+}
+
+pub fn save(path: &str, mesh: &Mesh) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out, mesh)?;
+    out.flush()
+}
+
+// Reinterprets `count` items of `T` at the start of `bytes`.
+//
+// Safety: `T` must be `repr(C)`, made only of 4-byte plain-old-data fields,
+// and valid for any bit pattern.
+unsafe fn cast<T>(bytes: &[u8], count: usize) -> std::io::Result<(&[T], &[u8])> {
+    let size = count
+        .checked_mul(std::mem::size_of::<T>())
+        .filter(|size| *size <= bytes.len())
+        .ok_or_else(|| invalid("truncated file"))?;
+    if bytes.as_ptr().align_offset(std::mem::align_of::<T>()) != 0 {
+        return Err(invalid("buffer is not 4-byte aligned"));
+    }
+    let (section, rest) = bytes.split_at(size);
+    Ok((
+        std::slice::from_raw_parts(section.as_ptr() as *const T, count),
+        rest,
+    ))
+}
+
+pub fn is_bmesh(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+// Borrows the mesh sections of `bytes` without copying. `bytes` must be
+// 4-byte aligned, which holds for memory maps and for `Vec<u32>`-backed
+// buffers.
+pub fn view(bytes: &[u8]) -> std::io::Result<MeshView<'_>> {
+    if cfg!(target_endian = "big") {
+        return Err(invalid("zero-copy view requires a little-endian host"));
+    }
+    if bytes.len() < 8 || !is_bmesh(bytes) {
+        return Err(invalid("bad magic"));
+    }
+    let header_size = match u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) {
+        1 => HEADER_SIZE - 4,
+        VERSION => HEADER_SIZE,
+        _ => return Err(invalid("unsupported version")),
+    };
+    if bytes.len() < header_size {
+        return Err(invalid("truncated file"));
+    }
+    let mut header: Vec<usize> = bytes[8..header_size]
+        .chunks(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        .collect();
+    header.resize(6, 0);
+    let (vcount, ncount, tccount, tcount, mcount, uvcount) = (
+        header[0], header[1], header[2], header[3], header[4], header[5],
+    );
+    if mcount != 0 && mcount != tcount {
+        return Err(invalid("one material id per triangle expected"));
+    }
+    if uvcount != 0 && uvcount != tcount {
+        return Err(invalid("one uv triangle per triangle expected"));
+    }
+    let rest = &bytes[header_size..];
+    // Safety: V3, Triangle and u32 are repr(C) aggregates of 4-byte scalars.
+    unsafe {
+        let (vertices, rest) = cast::<V3>(rest, vcount)?;
+        let (normals, rest) = cast::<V3>(rest, ncount)?;
+        let (texcoords, rest) = cast::<V3>(rest, tccount)?;
+        let (triangles, rest) = cast::<Triangle>(rest, tcount)?;
+        let (materials, rest) = cast::<u32>(rest, mcount)?;
+        let (uv_triangles, _rest) = cast::<Triangle>(rest, uvcount)?;
+        Ok(MeshView {
+            vertices,
+            normals,
+            texcoords,
+            triangles,
+            materials,
+            uv_triangles,
+        })
+    }
+}
+
+// A binary mesh file mapped in memory.
+pub struct MappedMesh {
+    map: memmap2::Mmap,
+}
+
+impl MappedMesh {
+    pub fn view(&self) -> std::io::Result<MeshView<'_>> {
+        view(&self.map)
+    }
+}
+
+pub fn map(path: &str) -> std::io::Result<MappedMesh> {
+    let file = File::open(path)?;
+    // Safety: the file must not be truncated while mapped.
+    let map = unsafe { memmap2::Mmap::map(&file)? };
+    Ok(MappedMesh { map })
+}
+
+// Copies the mesh out of the file at `path`. Readers that do not keep the
+// mesh can `map` the file and use its `view` instead.
+pub fn load(path: &str) -> std::io::Result<Mesh> {
+    Ok(map(path)?.view()?.to_mesh())
+}
+
+// Checks whether the file at `path` starts with the binary mesh magic.
+pub fn is_bmesh_file(path: &str) -> std::io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let mut fd = File::open(path)?;
+    let n = fd.read(&mut magic)?;
+    Ok(n == 4 && is_bmesh(&magic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Mesh {
+        Mesh {
+            vertices: vec![V3(0.0, 0.0, 0.0), V3(1.0, 0.0, 0.0), V3(0.0, 1.0, 0.5)],
+            normals: vec![V3(0.0, 0.0, 1.0)],
+            texcoords: vec![V3(0.0, 0.0, 0.0), V3(1.0, 1.0, 0.0)],
+            colors: Vec::new(),
+            triangles: vec![Triangle(1, 2, 3), Triangle(3, 2, 1)],
+            uv_triangles: vec![Triangle(1, 2, 1), crate::loader::NO_TEXCOORDS],
+            materials: vec![0, 7],
+            material_names: Vec::new(),
+            groups: Vec::new(),
+        }
+    }
+
+    // Copies `bytes` into a u32-aligned buffer.
+    fn aligned(bytes: &[u8]) -> Vec<u32> {
+        let mut words = vec![0u32; bytes.len().div_ceil(4)];
+        for (i, b) in bytes.iter().enumerate() {
+            words[i / 4] |= (*b as u32) << (8 * (i % 4));
+        }
+        words
+    }
+
+    fn as_bytes(words: &[u32], len: usize) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(words.as_ptr() as *const u8, len) }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let mesh = sample();
+        let mut bytes = Vec::new();
+        write(&mut bytes, &mesh).unwrap();
+        let words = aligned(&bytes);
+        let view = view(as_bytes(&words, bytes.len())).unwrap();
+        assert_eq!(view.vertices, &mesh.vertices[..]);
+        assert_eq!(view.normals, &mesh.normals[..]);
+        assert_eq!(view.texcoords, &mesh.texcoords[..]);
+        assert_eq!(view.triangles, &mesh.triangles[..]);
+        assert_eq!(view.materials, &mesh.materials[..]);
+        assert_eq!(view.uv_triangles, &mesh.uv_triangles[..]);
+    }
+
+    #[test]
+    fn test_version1() {
+        let mut mesh = sample();
+        mesh.uv_triangles.clear();
+        let mut bytes = Vec::new();
+        write(&mut bytes, &mesh).unwrap();
+        // drop the uv triangle count
+        bytes[4..8].copy_from_slice(&1u32.to_le_bytes());
+        bytes.drain(HEADER_SIZE - 4..HEADER_SIZE);
+        let words = aligned(&bytes);
+        let view = view(as_bytes(&words, bytes.len())).unwrap();
+        assert_eq!(view.to_mesh(), mesh);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut bytes = Vec::new();
+        write(&mut bytes, &sample()).unwrap();
+        let words = aligned(&bytes);
+        assert!(view(as_bytes(&words, bytes.len() - 4)).is_err());
+        assert!(view(as_bytes(&words, 3)).is_err());
+    }
+}
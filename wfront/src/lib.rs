@@ -1,12 +1,4 @@
+pub mod bmesh;
 pub mod loader;
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+pub mod ply;
+pub mod stl;
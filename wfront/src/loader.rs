@@ -1,42 +1,86 @@
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{char, multispace0, one_of},
-    combinator::{map, map_res, recognize},
+    character::complete::{char, multispace0, one_of, space1},
+    combinator::{all_consuming, map, map_res, opt, recognize, verify},
     error::ParseError,
-    multi::{many0, many1},
+    multi::{many0, many1, separated_list1},
     number::complete::float,
-    sequence::{delimited, preceded, separated_pair, terminated},
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
     IResult,
 };
 use std::fs::File;
-use std::io::Read;
+use std::io::{BufRead, BufWriter, Read, Write};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
 pub struct V3(pub f32, pub f32, pub f32);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
 pub struct Triangle(pub u32, pub u32, pub u32);
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Mesh {
     pub vertices: Vec<V3>,
-    pub normals: Vec<V3>,
+    pub normals: Vec<V3>, // indexed by faces in OBJ files, per vertex in PLY files
+    pub texcoords: Vec<V3>,
+    pub colors: Vec<V3>, // per-vertex RGB in [0, 1], empty if the source has none
     pub triangles: Vec<Triangle>,
+    // per-triangle 1-based indices in `texcoords`, `Triangle(0, 0, 0)` for
+    // faces without; empty if no face has texcoords
+    pub uv_triangles: Vec<Triangle>,
+    pub materials: Vec<u32>, // per-triangle material ids, empty if the source has none
+    pub material_names: Vec<String>, // indexed by `materials`, from `usemtl` lines
+    pub groups: Vec<Group>,  // empty if the source has no `o`, `g` or `s` lines
+}
+
+// Marks triangles without texcoords in `Mesh::uv_triangles`.
+pub const NO_TEXCOORDS: Triangle = Triangle(0, 0, 0);
+
+impl Mesh {
+    // See `MeshView::texcoord_indices`.
+    pub fn texcoord_indices(&self, i: usize) -> Option<Triangle> {
+        crate::bmesh::MeshView::from(self).texcoord_indices(i)
+    }
+}
+
+// A run of consecutive triangles sharing the same object (`o`), group (`g`)
+// and smoothing group (`s`, 0 when off). A group whose smoothing changes
+// midway appears as several consecutive runs. Runs cover all triangles,
+// those before the first `o`, `g` or `s` line forming an unnamed run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Group {
+    pub object: String,
+    pub name: String,
+    pub smoothing: u32,
+    pub start: usize, // inclusive
+    pub stop: usize,  // exclusive
 }
 
 enum Item {
     V(V3),
     VN(V3),
     VT(V3),
-    F(Triangle),
+    F(Vec<(i64, Option<i64>)>),
+    O(String),
+    G(String),
+    S(u32),
+    UseMtl(String),
+    MtlLib,
 }
 
 fn empty_mesh() -> Mesh {
     Mesh {
         vertices: Vec::new(),
         normals: Vec::new(),
+        texcoords: Vec::new(),
+        colors: Vec::new(),
         triangles: Vec::new(),
+        uv_triangles: Vec::new(),
+        materials: Vec::new(),
+        material_names: Vec::new(),
+        groups: Vec::new(),
     }
 }
 
@@ -56,44 +100,490 @@ where
     delimited(multispace0, inner, multispace0)
 }
 
+// Fields are separated by any run of spaces and tabs.
 pub fn parse_v3(input: &str) -> IResult<&str, V3> {
     let (remaining, (x, (y, z))) =
-        separated_pair(float, tag(" "), separated_pair(float, tag(" "), float))(input)?;
+        separated_pair(float, space1, separated_pair(float, space1, float))(input)?;
     Ok((remaining, V3(x, y, z)))
 }
 
-pub fn parse_triangle(input: &str) -> IResult<&str, Triangle> {
-    let (remaining, (t0, (t1, t2))) = separated_pair(
-        decimal,
-        tag(" "),
-        separated_pair(decimal, tag(" "), decimal),
-    )(input)?;
-    Ok((remaining, Triangle(t0, t1, t2)))
+fn index(input: &str) -> IResult<&str, i64> {
+    map(pair(opt(char('-')), decimal), |(sign, i)| match sign {
+        Some(_) => -(i as i64),
+        None => i as i64,
+    })(input)
 }
 
+// One corner of a face: `v`, `v/vt`, `v//vn` or `v/vt/vn`. The vertex and
+// texcoord indices are kept.
+fn face_vertex(input: &str) -> IResult<&str, (i64, Option<i64>)> {
+    map(
+        pair(
+            index,
+            opt(pair(
+                preceded(char('/'), opt(index)),
+                opt(preceded(char('/'), index)),
+            )),
+        ),
+        |(v, rest)| (v, rest.and_then(|(vt, _)| vt)),
+    )(input)
+}
+
+// Face indices as written in the file: 1-based, or negative to count back
+// from the last vertex (or texcoord) defined so far. Faces have at least
+// three corners.
+pub fn parse_face_indices(input: &str) -> IResult<&str, Vec<(i64, Option<i64>)>> {
+    verify(separated_list1(space1, face_vertex), |corners: &Vec<_>| {
+        corners.len() >= 3
+    })(input)
+}
+
+// `v x y z [w]`; the weight of rational vertices is ignored.
 fn parse_vertex(input: &str) -> IResult<&str, Item> {
-    map(preceded(ws(tag("v")), parse_v3), |x| Item::V(x))(input)
+    map(terminated(parse_v3, opt(preceded(space1, float))), Item::V)(input)
 }
 
+// `vt u [v [w]]`, missing components being 0.
 fn parse_vertex_texcoord(input: &str) -> IResult<&str, Item> {
-    map(preceded(ws(tag("vt")), parse_v3), |x| Item::VT(x))(input)
+    map(
+        pair(
+            float,
+            opt(pair(preceded(space1, float), opt(preceded(space1, float)))),
+        ),
+        |(u, vw)| match vw {
+            None => Item::VT(V3(u, 0.0, 0.0)),
+            Some((v, w)) => Item::VT(V3(u, v, w.unwrap_or(0.0))),
+        },
+    )(input)
 }
 
 fn parse_vertex_normal(input: &str) -> IResult<&str, Item> {
-    map(preceded(ws(tag("vn")), parse_v3), |x| Item::VN(x))(input)
+    map(parse_v3, Item::VN)(input)
 }
 
 fn parse_face(input: &str) -> IResult<&str, Item> {
-    map(preceded(ws(tag("f")), parse_triangle), |x| Item::F(x))(input)
+    map(parse_face_indices, Item::F)(input)
+}
+
+// `s N`, with `s off` and `s 0` both turning smoothing off.
+fn parse_smoothing(input: &str) -> IResult<&str, Item> {
+    map(alt((decimal, map(tag("off"), |_| 0))), Item::S)(input)
+}
+
+// Removes a trailing comment and surrounding whitespace, including the `\r`
+// of CRLF line endings.
+fn strip_line(line: &str) -> &str {
+    let end = line.find('#').unwrap_or(line.len());
+    line[..end].trim()
 }
 
+// Splits a line into its leading keyword and the rest of the line.
+fn keyword(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+    let end = line.find(char::is_whitespace).unwrap_or(line.len());
+    line.split_at(end)
+}
+
+// Parses a line stripped by `strip_line`. The whole line must be consumed.
 fn parse_line(input: &str) -> IResult<&str, Item> {
-    alt((
-        parse_vertex,
-        parse_vertex_texcoord,
-        parse_vertex_normal,
-        parse_face,
-    ))(input)
+    let (kw, rest) = keyword(input);
+    let rest = rest.trim_start();
+    match kw {
+        "v" => all_consuming(parse_vertex)(rest),
+        "vt" => all_consuming(parse_vertex_texcoord)(rest),
+        "vn" => all_consuming(parse_vertex_normal)(rest),
+        "f" => all_consuming(parse_face)(rest),
+        "o" => Ok(("", Item::O(rest.to_string()))),
+        "g" => Ok(("", Item::G(rest.to_string()))),
+        "s" => all_consuming(parse_smoothing)(rest),
+        "usemtl" => Ok(("", Item::UseMtl(rest.to_string()))),
+        // material libraries are not read
+        "mtllib" => Ok(("", Item::MtlLib)),
+        _ => Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
+
+#[derive(Debug)]
+pub struct LoadError {
+    pub line: usize, // 1-based
+    pub message: String,
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+impl From<LoadError> for std::io::Error {
+    fn from(e: LoadError) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+// Number of lines and of each kind of indexable element in a piece of file.
+#[derive(Clone, Copy, Debug, Default)]
+struct Counts {
+    lines: usize,
+    vertices: usize,
+    texcoords: usize,
+    normals: usize,
+}
+
+fn lines(buf: &[u8]) -> impl Iterator<Item = &[u8]> {
+    // A trailing newline does not start an extra line.
+    let buf = buf.strip_suffix(b"\n").unwrap_or(buf);
+    buf.split(|&c| c == b'\n')
+}
+
+// Cheap pre-pass giving the counts a chunk contributes, so that chunks can
+// be parsed independently while resolving relative indices.
+fn count(chunk: &[u8]) -> Counts {
+    let mut counts = Counts::default();
+    for line in lines(chunk) {
+        counts.lines += 1;
+        let line = match line.iter().position(|&c| c == b'#') {
+            Some(end) => &line[..end],
+            None => line,
+        };
+        let start = line
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .unwrap_or(line.len());
+        let line = &line[start..];
+        let end = line
+            .iter()
+            .position(|c| c.is_ascii_whitespace())
+            .unwrap_or(line.len());
+        match &line[..end] {
+            b"v" => counts.vertices += 1,
+            b"vt" => counts.texcoords += 1,
+            b"vn" => counts.normals += 1,
+            _ => (),
+        }
+    }
+    counts
+}
+
+// Start of a run of triangles in a chunk. `None` fields are inherited from
+// the lines preceding the chunk, which it cannot see.
+#[derive(Clone, Default)]
+struct Run {
+    object: Option<String>,
+    name: Option<String>,
+    smoothing: Option<u32>,
+    start: usize,
+}
+
+// Parser state for a contiguous range of lines. `base` holds the counts of
+// everything before the range, so a chunk parses exactly as it would as
+// part of the whole file.
+struct Chunk {
+    base: Counts,
+    mesh: Mesh,
+    runs: Vec<Run>,
+    // index in `mesh.material_names` of the material in use
+    material: Option<u32>,
+}
+
+// Material id of triangles preceding the chunk's first `usemtl`.
+const INHERITED: u32 = u32::MAX;
+
+impl Chunk {
+    fn new(base: Counts) -> Self {
+        Chunk {
+            base,
+            mesh: empty_mesh(),
+            runs: Vec::new(),
+            material: None,
+        }
+    }
+
+    // Run starting at the next triangle, carrying over the current state.
+    fn start_run(&mut self) -> &mut Run {
+        let start = self.mesh.triangles.len();
+        match self.runs.last() {
+            Some(run) if run.start == start => (),
+            last => {
+                let run = Run {
+                    start,
+                    ..last.cloned().unwrap_or_default()
+                };
+                self.runs.push(run)
+            }
+        }
+        self.runs.last_mut().unwrap()
+    }
+
+    // `defined` is the number of vertices (or texcoords) so far.
+    fn resolve(index: i64, defined: usize, what: &str, line: usize) -> Result<u32, LoadError> {
+        let resolved = if index < 0 {
+            defined as i64 + index + 1
+        } else {
+            index
+        };
+        if resolved <= 0 || resolved > u32::MAX as i64 {
+            Err(LoadError {
+                line,
+                message: format!("invalid {what} index {index}"),
+            })
+        } else {
+            Ok(resolved as u32)
+        }
+    }
+
+    fn push_line(&mut self, line: &[u8], line_number: usize) -> Result<(), LoadError> {
+        let line_as_str = std::str::from_utf8(line).map_err(|e| LoadError {
+            line: line_number,
+            message: e.to_string(),
+        })?;
+        let line_as_str = strip_line(line_as_str);
+        if line_as_str.is_empty() {
+            return Ok(());
+        }
+        let (_remaining, parse_result) = parse_line(line_as_str).map_err(|e| LoadError {
+            line: line_number,
+            message: format!("cannot parse {line_as_str:?}: {e}"),
+        })?;
+        match parse_result {
+            Item::V(v) => self.mesh.vertices.push(v),
+            Item::F(corners) => {
+                let vertices = self.base.vertices + self.mesh.vertices.len();
+                let texcoords = self.base.texcoords + self.mesh.texcoords.len();
+                let mut uvs = Vec::with_capacity(corners.len());
+                let corners = corners
+                    .into_iter()
+                    .map(|(v, vt)| {
+                        if let Some(vt) = vt {
+                            // references to undefined texcoords are dropped
+                            let vt = Self::resolve(vt, texcoords, "texcoord", line_number)?;
+                            if vt as usize <= texcoords {
+                                uvs.push(vt);
+                            }
+                        }
+                        Self::resolve(v, vertices, "vertex", line_number)
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                // polygons are triangulated as fans around their first
+                // corner; faces with texcoords on only some corners have none
+                for i in 1..corners.len() - 1 {
+                    let t = Triangle(corners[0], corners[i], corners[i + 1]);
+                    self.mesh.triangles.push(t);
+                    let uv = if uvs.len() == corners.len() {
+                        Triangle(uvs[0], uvs[i], uvs[i + 1])
+                    } else {
+                        NO_TEXCOORDS
+                    };
+                    self.mesh.uv_triangles.push(uv);
+                    self.mesh.materials.push(self.material.unwrap_or(INHERITED));
+                }
+            }
+            Item::VN(v) => self.mesh.normals.push(v),
+            Item::VT(v) => self.mesh.texcoords.push(v),
+            Item::O(object) => self.start_run().object = Some(object),
+            Item::G(name) => self.start_run().name = Some(name),
+            Item::S(smoothing) => self.start_run().smoothing = Some(smoothing),
+            Item::UseMtl(name) => {
+                let names = &mut self.mesh.material_names;
+                let id = match names.iter().position(|n| *n == name) {
+                    Some(id) => id,
+                    None => {
+                        names.push(name);
+                        names.len() - 1
+                    }
+                };
+                self.material = Some(id as u32)
+            }
+            Item::MtlLib => (),
+        }
+        Ok(())
+    }
+
+    fn parse(&mut self, buf: &[u8]) -> Result<(), LoadError> {
+        for (i, line) in lines(buf).enumerate() {
+            self.push_line(line, self.base.lines + i + 1)?;
+        }
+        Ok(())
+    }
+}
+
+fn merge(chunks: Vec<Chunk>) -> Mesh {
+    let mut mesh = empty_mesh();
+    let mut groups: Vec<Group> = Vec::new();
+    // material in use at the end of the previous chunk; triangles before
+    // the first `usemtl` get the first material
+    let mut material = 0;
+    for mut chunk in chunks {
+        let offset = mesh.triangles.len();
+        let remap: Vec<u32> = chunk
+            .mesh
+            .material_names
+            .drain(..)
+            .map(|name| {
+                let names = &mut mesh.material_names;
+                match names.iter().position(|n| *n == name) {
+                    Some(id) => id as u32,
+                    None => {
+                        names.push(name);
+                        (names.len() - 1) as u32
+                    }
+                }
+            })
+            .collect();
+        for m in chunk.mesh.materials.iter_mut() {
+            *m = match *m {
+                INHERITED => material,
+                local => remap[local as usize],
+            }
+        }
+        if let Some(last) = chunk.material {
+            material = remap[last as usize];
+        }
+        for run in chunk.runs {
+            let start = offset + run.start;
+            if groups.is_empty() && start > 0 {
+                groups.push(Group::default());
+            }
+            let prev = groups.last().cloned().unwrap_or_default();
+            groups.push(Group {
+                object: run.object.unwrap_or(prev.object),
+                name: run.name.unwrap_or(prev.name),
+                smoothing: run.smoothing.unwrap_or(prev.smoothing),
+                start,
+                stop: start,
+            });
+        }
+        mesh.vertices.append(&mut chunk.mesh.vertices);
+        mesh.normals.append(&mut chunk.mesh.normals);
+        mesh.texcoords.append(&mut chunk.mesh.texcoords);
+        mesh.colors.append(&mut chunk.mesh.colors);
+        mesh.triangles.append(&mut chunk.mesh.triangles);
+        mesh.uv_triangles.append(&mut chunk.mesh.uv_triangles);
+        mesh.materials.append(&mut chunk.mesh.materials);
+    }
+    // Each run extends to the next one; drop empty runs and join runs left
+    // identical once inherited fields are resolved.
+    let total = mesh.triangles.len();
+    for i in 0..groups.len() {
+        groups[i].stop = groups.get(i + 1).map_or(total, |next| next.start);
+    }
+    groups.retain(|group| group.start < group.stop);
+    groups.dedup_by(|next, prev| {
+        let same = (&next.object, &next.name, next.smoothing)
+            == (&prev.object, &prev.name, prev.smoothing);
+        if same {
+            prev.stop = next.stop;
+        }
+        same
+    });
+    mesh.groups = groups;
+    if mesh.material_names.is_empty() {
+        mesh.materials.clear();
+    }
+    if mesh.uv_triangles.iter().all(|t| *t == NO_TEXCOORDS) {
+        mesh.uv_triangles.clear();
+    }
+    mesh
+}
+
+// Splits `buf` into at most `n` pieces, cutting only after newlines.
+fn split_lines(buf: &[u8], n: usize) -> Vec<&[u8]> {
+    let mut pieces = Vec::with_capacity(n);
+    let mut start = 0;
+    for i in 1..n {
+        let target = (buf.len() * i / n).max(start);
+        let cut = match buf[target..].iter().position(|&c| c == b'\n') {
+            Some(pos) => target + pos + 1,
+            None => buf.len(),
+        };
+        if cut > start {
+            pieces.push(&buf[start..cut]);
+            start = cut;
+        }
+    }
+    if start < buf.len() || pieces.is_empty() {
+        pieces.push(&buf[start..]);
+    }
+    pieces
+}
+
+// Parses `buf` as `chunks` independent pieces on as many threads. The result
+// does not depend on the number of chunks.
+pub fn parse_chunked(buf: &[u8], chunks: usize) -> Result<Mesh, LoadError> {
+    let pieces = split_lines(buf, chunks.max(1));
+    if pieces.len() == 1 {
+        let mut chunk = Chunk::new(Counts::default());
+        chunk.parse(pieces[0])?;
+        return Ok(merge(vec![chunk]));
+    }
+
+    let counts: Vec<Counts> = std::thread::scope(|s| {
+        let handles: Vec<_> = pieces
+            .iter()
+            .map(|piece| s.spawn(move || count(piece)))
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut base = Counts::default();
+    let mut parsed: Vec<Chunk> = Vec::with_capacity(pieces.len());
+    for c in counts.iter() {
+        parsed.push(Chunk::new(base));
+        base.lines += c.lines;
+        base.vertices += c.vertices;
+        base.texcoords += c.texcoords;
+        base.normals += c.normals;
+    }
+
+    std::thread::scope(|s| {
+        let handles: Vec<_> = parsed
+            .iter_mut()
+            .zip(pieces.iter())
+            .map(|(chunk, piece)| s.spawn(move || chunk.parse(piece)))
+            .collect();
+        // Report the first error in file order.
+        handles.into_iter().try_for_each(|h| h.join().unwrap())
+    })?;
+
+    Ok(merge(parsed))
+}
+
+// Files smaller than this are not worth spreading over several threads.
+const MIN_CHUNK_SIZE: usize = 1 << 20;
+
+pub fn parse(buf: &[u8]) -> Result<Mesh, LoadError> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunks = (buf.len() / MIN_CHUNK_SIZE).clamp(1, threads);
+    parse_chunked(buf, chunks)
+}
+
+// Parses a mesh line by line as it is read, without buffering the whole
+// input. Parse errors are reported as `InvalidData`.
+pub fn load_from_reader<R: BufRead>(mut reader: R) -> std::io::Result<Mesh> {
+    let mut chunk = Chunk::new(Counts::default());
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        line_number += 1;
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        chunk.push_line(text, line_number)?;
+    }
+    Ok(merge(vec![chunk]))
+}
+
+pub fn load_from_str(input: &str) -> Result<Mesh, LoadError> {
+    let mut chunk = Chunk::new(Counts::default());
+    chunk.parse(input.as_bytes())?;
+    Ok(merge(vec![chunk]))
 }
 
 pub fn load(filename: &str) -> Mesh {
@@ -102,26 +592,382 @@ pub fn load(filename: &str) -> Mesh {
     let mut fd = File::open(filename).unwrap();
     fd.read_to_end(&mut buf).unwrap();
 
-    let mut mesh = empty_mesh();
+    match parse(&buf) {
+        Ok(mesh) => mesh,
+        Err(e) => panic!("{filename}: {e}"),
+    }
+}
 
-    buf.split(|&c| c == b'\n')
-        .into_iter()
-        .fold((), |_acc, line| {
-            if line.len() == 0 {
-                ()
-            } else if line[0] == b'#' {
-                ()
-            } else {
-                let line_as_str = std::str::from_utf8(line).unwrap();
-                let (_remaining, parse_result) = parse_line(line_as_str).unwrap();
-                match parse_result {
-                    Item::V(v) => mesh.vertices.push(v),
-                    Item::F(t) => mesh.triangles.push(t),
-                    Item::VN(v) => mesh.normals.push(v),
-                    Item::VT(_) => (),
+// Material of an MTL library, written by `write_mtl`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtlMaterial {
+    pub name: String,
+    pub diffuse: V3,    // Kd
+    pub specular: V3,   // Ks
+    pub shininess: f32, // Ns
+}
+
+fn write_v3<W: Write>(out: &mut W, keyword: &str, V3(x, y, z): &V3) -> std::io::Result<()> {
+    writeln!(out, "{keyword} {x} {y} {z}")
+}
+
+// Writes `mesh` in Wavefront format. Texcoords are referenced by the faces
+// as given by `Mesh::texcoord_indices`, and normals when there is one per
+// vertex (as in PLY files); others are written unreferenced. Groups become `o`, `g` and `s` lines, and
+// materials `usemtl` lines naming `mesh.material_names`, or `material{id}`
+// when names are missing. `mtllib` names the material library, if any.
+pub fn write<W: Write>(out: &mut W, mesh: &Mesh, mtllib: Option<&str>) -> std::io::Result<()> {
+    if let Some(mtllib) = mtllib {
+        writeln!(out, "mtllib {mtllib}")?;
+    }
+    for v in &mesh.vertices {
+        write_v3(out, "v", v)?;
+    }
+    for V3(u, v, w) in &mesh.texcoords {
+        writeln!(out, "vt {u} {v} {w}")?;
+    }
+    for n in &mesh.normals {
+        write_v3(out, "vn", n)?;
+    }
+
+    let normals = !mesh.normals.is_empty() && mesh.normals.len() == mesh.vertices.len();
+    let corner = |v: u32, vt: Option<u32>| match (vt, normals) {
+        (None, false) => format!("{v}"),
+        (Some(vt), false) => format!("{v}/{vt}"),
+        (None, true) => format!("{v}//{v}"),
+        (Some(vt), true) => format!("{v}/{vt}/{v}"),
+    };
+    let material_name = |id: u32| match mesh.material_names.get(id as usize) {
+        Some(name) => name.clone(),
+        None => format!("material{id}"),
+    };
+
+    let mut groups = mesh.groups.iter().peekable();
+    // object, group, smoothing group and material in effect
+    let mut current = (Some(""), Some(""), Some(0), None);
+    for (i, Triangle(t0, t1, t2)) in mesh.triangles.iter().enumerate() {
+        if let Some(group) = groups.next_if(|g| g.start == i) {
+            if current.0 != Some(&group.object) {
+                writeln!(out, "o {}", group.object)?;
+                current.0 = Some(&group.object);
+            }
+            if current.1 != Some(&group.name) {
+                writeln!(out, "g {}", group.name)?;
+                current.1 = Some(&group.name);
+            }
+            if current.2 != Some(group.smoothing) {
+                match group.smoothing {
+                    0 => writeln!(out, "s off")?,
+                    s => writeln!(out, "s {s}")?,
                 }
+                current.2 = Some(group.smoothing);
             }
-        });
+        }
+        if let Some(&mat) = mesh.materials.get(i) {
+            if current.3 != Some(mat) {
+                writeln!(out, "usemtl {}", material_name(mat))?;
+                current.3 = Some(mat);
+            }
+        }
+        let [vt0, vt1, vt2] = match mesh.texcoord_indices(i) {
+            Some(Triangle(vt0, vt1, vt2)) => [Some(vt0), Some(vt1), Some(vt2)],
+            None => [None; 3],
+        };
+        let (c0, c1, c2) = (corner(*t0, vt0), corner(*t1, vt1), corner(*t2, vt2));
+        writeln!(out, "f {c0} {c1} {c2}")?;
+    }
+    Ok(())
+}
 
-    mesh
+pub fn write_mtl<W: Write>(out: &mut W, materials: &[MtlMaterial]) -> std::io::Result<()> {
+    for (i, m) in materials.iter().enumerate() {
+        if i > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "newmtl {}", m.name)?;
+        write_v3(out, "Kd", &m.diffuse)?;
+        write_v3(out, "Ks", &m.specular)?;
+        writeln!(out, "Ns {}", m.shininess)?;
+    }
+    Ok(())
+}
+
+// Writes `mesh` to `path` and, when `materials` is not empty, the material
+// library next to it, with the `.mtl` extension.
+pub fn save(path: &str, mesh: &Mesh, materials: &[MtlMaterial]) -> std::io::Result<()> {
+    let mtl_path = std::path::Path::new(path).with_extension("mtl");
+    let mtllib = match materials {
+        [] => None,
+        _ => {
+            let mut out = BufWriter::new(File::create(&mtl_path)?);
+            write_mtl(&mut out, materials)?;
+            out.flush()?;
+            mtl_path.file_name().and_then(|name| name.to_str())
+        }
+    };
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out, mesh, mtllib)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RELATIVE: &str = "v 0 0 0
+v 1 0 0
+v 0 1 0
+f -3 -2 -1
+# comment
+v 0 0 1
+
+f 1 2 4
+f -4 -1 -2
+v 1 1 1
+f -1 -2 -3
+";
+
+    #[test]
+    fn test_relative_indices() {
+        let mesh = parse_chunked(RELATIVE.as_bytes(), 1).unwrap();
+        assert_eq!(
+            mesh.triangles,
+            vec![
+                Triangle(1, 2, 3),
+                Triangle(1, 2, 4),
+                Triangle(1, 4, 3),
+                Triangle(5, 4, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_chunked_matches_serial() {
+        let serial = parse_chunked(RELATIVE.as_bytes(), 1).unwrap();
+        for chunks in 2..16 {
+            assert_eq!(parse_chunked(RELATIVE.as_bytes(), chunks).unwrap(), serial);
+        }
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../buddha.wobj");
+        let buf = std::fs::read(path).unwrap();
+        let serial = parse_chunked(&buf, 1).unwrap();
+        assert_eq!(serial.triangles.len(), 100000);
+        assert_eq!(parse_chunked(&buf, 7).unwrap(), serial);
+    }
+
+    #[test]
+    fn test_load_from_reader() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../sphere2.obj");
+        let buf = std::fs::read(path).unwrap();
+        // a small buffer makes lines straddle refills
+        let reader = std::io::BufReader::with_capacity(7, &buf[..]);
+        assert_eq!(load_from_reader(reader).unwrap(), parse(&buf).unwrap());
+        assert_eq!(
+            load_from_reader(RELATIVE.as_bytes()).unwrap(),
+            load_from_str(RELATIVE).unwrap()
+        );
+        let err = load_from_reader("v 0 0 0\nvx 1\n".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("line 2:"), "{err}");
+    }
+
+    const CORPUS: [&str; 9] = [
+        "single_space.obj",
+        "whitespace.obj",
+        "crlf.obj",
+        "comments.obj",
+        "weights.obj",
+        "texcoords.obj",
+        "polygon.obj",
+        "numbers.obj",
+        "groups.obj",
+    ];
+
+    fn load_corpus(name: &str) -> Mesh {
+        let path = format!("{}/corpus/{name}", env!("CARGO_MANIFEST_DIR"));
+        let buf = std::fs::read(path).unwrap();
+        parse(&buf).unwrap_or_else(|e| panic!("{name}: {e}"))
+    }
+
+    // Every corpus file describes the same unit quad.
+    #[test]
+    fn test_corpus() {
+        let quad = [
+            V3(0.0, 0.0, 0.0),
+            V3(1.0, 0.0, 0.0),
+            V3(1.0, 1.0, 0.0),
+            V3(0.0, 1.0, 0.0),
+        ];
+        for name in CORPUS {
+            let mesh = load_corpus(name);
+            assert_eq!(mesh.vertices, quad, "{name}");
+            assert_eq!(
+                mesh.triangles,
+                vec![Triangle(1, 2, 3), Triangle(1, 3, 4)],
+                "{name}"
+            );
+        }
+        let mesh = load_corpus("texcoords.obj");
+        assert_eq!(
+            mesh.texcoords,
+            vec![
+                V3(0.0, 0.0, 0.0),
+                V3(1.0, 0.0, 0.0),
+                V3(1.0, 1.0, 0.0),
+                V3(0.5, 0.0, 0.0)
+            ]
+        );
+        assert_eq!(mesh.normals, vec![V3(0.0, 0.0, 1.0)]);
+        assert_eq!(mesh.uv_triangles, vec![Triangle(1, 2, 3), NO_TEXCOORDS]);
+        assert_eq!(mesh.texcoord_indices(0), Some(Triangle(1, 2, 3)));
+        assert_eq!(mesh.texcoord_indices(1), None);
+        assert!(load_corpus("polygon.obj").uv_triangles.is_empty());
+    }
+
+    #[test]
+    fn test_texcoord_indices() {
+        let obj = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1
+f 1 2 3\nf 1/-4 2/-3 3/-2 4/-1\nf 1/1 2 3\n";
+        let serial = parse_chunked(obj.as_bytes(), 1).unwrap();
+        assert_eq!(
+            serial.uv_triangles,
+            vec![
+                NO_TEXCOORDS,
+                Triangle(1, 2, 3),
+                Triangle(1, 3, 4),
+                NO_TEXCOORDS
+            ]
+        );
+        for chunks in 2..12 {
+            assert_eq!(parse_chunked(obj.as_bytes(), chunks).unwrap(), serial);
+        }
+        assert!(load_from_str("v 0 0 0\nvt 0 0\nf 1/-2 1/1 1/1\n").is_err());
+    }
+
+    fn group(object: &str, name: &str, smoothing: u32, start: usize, stop: usize) -> Group {
+        Group {
+            object: object.to_string(),
+            name: name.to_string(),
+            smoothing,
+            start,
+            stop,
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        assert_eq!(
+            load_corpus("groups.obj").groups,
+            vec![
+                group("quad", "top", 1, 0, 1),
+                group("quad", "bottom", 0, 1, 2)
+            ]
+        );
+        assert!(load_corpus("single_space.obj").groups.is_empty());
+
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../cube.obj");
+        let cube = load(path);
+        assert_eq!(cube.triangles.len(), 12);
+        assert_eq!(cube.groups, vec![group("cube", "cube", 0, 0, 12)]);
+    }
+
+    #[test]
+    fn test_groups_across_chunks() {
+        let mut obj = String::from("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n");
+        for i in 0..20 {
+            match i % 4 {
+                0 => obj += &format!("o object{i}\n"),
+                1 => obj += &format!("g group{i}\n"),
+                2 => obj += &format!("s {}\n", i % 3),
+                _ => obj += "s off\ng group1\n",
+            }
+            for _ in 0..i % 3 {
+                obj += "f 1 2 3\nf -1 -2 -3\n";
+            }
+        }
+        let serial = parse_chunked(obj.as_bytes(), 1).unwrap();
+        assert_eq!(serial.groups[0], group("", "", 0, 0, 1));
+        assert_eq!(serial.groups.last().unwrap().stop, serial.triangles.len());
+        for chunks in 2..24 {
+            assert_eq!(parse_chunked(obj.as_bytes(), chunks).unwrap(), serial);
+        }
+    }
+
+    #[test]
+    fn test_materials() {
+        let obj = "mtllib lib.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nusemtl b\nf 1 2 3
+usemtl a\nf 1 2 3\nusemtl b\nf 1 2 3\n";
+        let serial = parse_chunked(obj.as_bytes(), 1).unwrap();
+        assert_eq!(serial.material_names, vec!["b", "a"]);
+        assert_eq!(serial.materials, vec![0, 0, 1, 0]);
+        for chunks in 2..12 {
+            assert_eq!(parse_chunked(obj.as_bytes(), chunks).unwrap(), serial);
+        }
+        assert!(load_corpus("groups.obj").materials.is_empty());
+    }
+
+    #[test]
+    fn test_write_roundtrip() {
+        let mut meshes: Vec<Mesh> = CORPUS.iter().map(|name| load_corpus(name)).collect();
+        // ids numbered by first use, as the loader does
+        let mut with_materials = load_corpus("groups.obj");
+        with_materials.materials = vec![0, 1];
+        with_materials.material_names = vec!["red".to_string(), "blue".to_string()];
+        meshes.push(with_materials);
+        for mesh in meshes {
+            let mut out = Vec::new();
+            write(&mut out, &mesh, Some("lib.mtl")).unwrap();
+            let text = String::from_utf8(out).unwrap();
+            assert_eq!(load_from_str(&text).unwrap(), mesh, "{text}");
+        }
+    }
+
+    #[test]
+    fn test_write_mtl() {
+        let materials = [
+            MtlMaterial {
+                name: "red".to_string(),
+                diffuse: V3(1.0, 0.0, 0.0),
+                specular: V3(0.5, 0.5, 0.5),
+                shininess: 10.0,
+            },
+            MtlMaterial {
+                name: "white".to_string(),
+                diffuse: V3(1.0, 1.0, 1.0),
+                specular: V3(0.0, 0.0, 0.0),
+                shininess: 0.0,
+            },
+        ];
+        let mut out = Vec::new();
+        write_mtl(&mut out, &materials).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "newmtl red\nKd 1 0 0\nKs 0.5 0.5 0.5\nNs 10\n\nnewmtl white\nKd 1 1 1\nKs 0 0 0\nNs 0\n"
+        );
+    }
+
+    #[test]
+    fn test_rejects_malformed_lines() {
+        for line in [
+            "v 1 2",
+            "v 1 2 3 4 5",
+            "vn 1 2",
+            "vt",
+            "f 1 2",
+            "f 1 2 3x",
+            "f 1/2/3/4 2 3",
+            "v 1,0 2 3",
+            "s on",
+        ] {
+            assert!(load_from_str(line).is_err(), "{line:?}");
+        }
+    }
+
+    #[test]
+    fn test_error_line_numbers() {
+        let buf = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 -4\n";
+        for chunks in 1..4 {
+            let err = parse_chunked(buf.as_bytes(), chunks).unwrap_err();
+            assert_eq!(err.line, 4);
+        }
+    }
 }
@@ -0,0 +1,592 @@
+use crate::loader::{Mesh, Triangle, V3};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+// Stanford PLY reader.
+//
+// Reads the `vertex` element (x, y, z, and optionally nx, ny, nz,
+// red, green, blue and u, v or s, t) and the `face` element (a
+// `vertex_indices` or `vertex_index` list, polygons being triangulated as
+// fans). Other elements and properties are skipped. Normals, colors and
+// texcoords are per vertex; integer colors are scaled to [0, 1] by the
+// maximum of their type.
+
+pub const MAGIC: &[u8; 3] = b"ply";
+
+fn invalid(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("ply: {what}"))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Ascii,
+    BinaryLe,
+    BinaryBe,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> std::io::Result<Scalar> {
+        match name {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            _ => Err(invalid(&format!("unknown type {name}"))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // Scale mapping the type's range to [0, 1], for colors.
+    fn unit(self) -> f64 {
+        match self {
+            Scalar::I8 => i8::MAX as f64,
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::I16 => i16::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            Scalar::I32 => i32::MAX as f64,
+            Scalar::U32 => u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar(String, Scalar),
+    List(String, Scalar, Scalar), // name, count type, item type
+}
+
+impl Property {
+    fn name(&self) -> &str {
+        match self {
+            Property::Scalar(name, _) | Property::List(name, _, _) => name,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+fn read_header<R: BufRead>(reader: &mut R) -> std::io::Result<Header> {
+    let mut line = Vec::new();
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut first = true;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Err(invalid("missing end_header"));
+        }
+        let text = std::str::from_utf8(&line).map_err(|_| invalid("header is not UTF-8"))?;
+        let words: Vec<&str> = text.split_whitespace().collect();
+        if first {
+            if words != ["ply"] {
+                return Err(invalid("bad magic"));
+            }
+            first = false;
+            continue;
+        }
+        match words.as_slice() {
+            ["format", kind, "1.0"] => {
+                format = Some(match *kind {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLe,
+                    "binary_big_endian" => Format::BinaryBe,
+                    _ => return Err(invalid(&format!("unknown format {kind}"))),
+                })
+            }
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid("bad element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("property outside of an element"))?
+                .properties
+                .push(Property::List(
+                    name.to_string(),
+                    Scalar::parse(count)?,
+                    Scalar::parse(item)?,
+                )),
+            ["property", ty, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid("property outside of an element"))?
+                .properties
+                .push(Property::Scalar(name.to_string(), Scalar::parse(ty)?)),
+            ["end_header"] => break,
+            _ => return Err(invalid(&format!("bad header line {:?}", text.trim()))),
+        }
+    }
+    let format = format.ok_or_else(|| invalid("missing format"))?;
+    Ok(Header { format, elements })
+}
+
+// Values of the body, read one at a time whatever the encoding.
+struct Body<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Body<'_> {
+    fn word(&mut self) -> std::io::Result<&str> {
+        let rest = &self.bytes[self.pos..];
+        let start = rest
+            .iter()
+            .position(|c| !c.is_ascii_whitespace())
+            .ok_or_else(|| invalid("truncated file"))?;
+        let len = rest[start..]
+            .iter()
+            .position(|c| c.is_ascii_whitespace())
+            .unwrap_or(rest.len() - start);
+        self.pos += start + len;
+        std::str::from_utf8(&rest[start..start + len]).map_err(|_| invalid("bad number"))
+    }
+
+    fn bytes<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        let end = self.pos + N;
+        let b = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or_else(|| invalid("truncated file"))?;
+        self.pos = end;
+        let mut out = [0u8; N];
+        out.copy_from_slice(b);
+        if self.format == Format::BinaryBe {
+            out.reverse();
+        }
+        Ok(out)
+    }
+
+    // Reads a value, converted to f64 (which holds all PLY scalars exactly).
+    fn value(&mut self, ty: Scalar) -> std::io::Result<f64> {
+        if self.format == Format::Ascii {
+            let word = self.word()?;
+            return word
+                .parse::<f64>()
+                .map_err(|_| invalid(&format!("bad number {word:?}")));
+        }
+        Ok(match ty {
+            Scalar::I8 => i8::from_le_bytes(self.bytes()?) as f64,
+            Scalar::U8 => u8::from_le_bytes(self.bytes()?) as f64,
+            Scalar::I16 => i16::from_le_bytes(self.bytes()?) as f64,
+            Scalar::U16 => u16::from_le_bytes(self.bytes()?) as f64,
+            Scalar::I32 => i32::from_le_bytes(self.bytes()?) as f64,
+            Scalar::U32 => u32::from_le_bytes(self.bytes()?) as f64,
+            Scalar::F32 => f32::from_le_bytes(self.bytes()?) as f64,
+            Scalar::F64 => f64::from_le_bytes(self.bytes()?),
+        })
+    }
+
+    fn skip(&mut self, property: &Property) -> std::io::Result<()> {
+        match property {
+            Property::Scalar(_, ty) => {
+                self.value(*ty)?;
+            }
+            Property::List(_, count, item) => {
+                let n = self.value(*count)? as usize;
+                if self.format == Format::Ascii {
+                    for _ in 0..n {
+                        self.word()?;
+                    }
+                } else {
+                    let size = n.saturating_mul(item.size());
+                    if size > self.bytes.len() - self.pos {
+                        return Err(invalid("truncated file"));
+                    }
+                    self.pos += size;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Position in an element's property list of each of `names`, if they are
+// all present as scalars.
+fn find_scalars<const N: usize>(element: &Element, names: [&str; N]) -> Option<[usize; N]> {
+    let mut out = [0; N];
+    for (slot, name) in out.iter_mut().zip(names) {
+        *slot = element
+            .properties
+            .iter()
+            .position(|p| matches!(p, Property::Scalar(n, _) if n == name))?;
+    }
+    Some(out)
+}
+
+fn read_vertices(body: &mut Body, element: &Element, mesh: &mut Mesh) -> std::io::Result<()> {
+    let position =
+        find_scalars(element, ["x", "y", "z"]).ok_or_else(|| invalid("vertices lack x, y or z"))?;
+    let normal = find_scalars(element, ["nx", "ny", "nz"]);
+    let color = find_scalars(element, ["red", "green", "blue"]);
+    let texcoord = find_scalars(element, ["u", "v"]).or_else(|| find_scalars(element, ["s", "t"]));
+    let color_unit: Vec<f64> = match color {
+        Some(c) => c
+            .iter()
+            .map(|i| match element.properties[*i] {
+                Property::Scalar(_, ty) => ty.unit(),
+                Property::List(..) => unreachable!(),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    let mut values = vec![0.0; element.properties.len()];
+    for _ in 0..element.count {
+        for (value, property) in values.iter_mut().zip(&element.properties) {
+            match property {
+                Property::Scalar(_, ty) => *value = body.value(*ty)?,
+                list => body.skip(list)?,
+            }
+        }
+        let v3 = |[x, y, z]: [usize; 3]| V3(values[x] as f32, values[y] as f32, values[z] as f32);
+        mesh.vertices.push(v3(position));
+        if let Some(normal) = normal {
+            mesh.normals.push(v3(normal));
+        }
+        if let Some([u, v]) = texcoord {
+            mesh.texcoords
+                .push(V3(values[u] as f32, values[v] as f32, 0.0));
+        }
+        if let Some([r, g, b]) = color {
+            mesh.colors.push(V3(
+                (values[r] / color_unit[0]) as f32,
+                (values[g] / color_unit[1]) as f32,
+                (values[b] / color_unit[2]) as f32,
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(body: &mut Body, element: &Element, mesh: &mut Mesh) -> std::io::Result<()> {
+    let indices = element
+        .properties
+        .iter()
+        .position(|p| {
+            matches!(p, Property::List(..)) && matches!(p.name(), "vertex_indices" | "vertex_index")
+        })
+        .ok_or_else(|| invalid("faces lack vertex_indices"))?;
+
+    let vcount = mesh.vertices.len();
+    let mut corners: Vec<u32> = Vec::new();
+    for _ in 0..element.count {
+        for (i, property) in element.properties.iter().enumerate() {
+            match property {
+                Property::List(_, count, item) if i == indices => {
+                    let n = body.value(*count)? as usize;
+                    corners.clear();
+                    for _ in 0..n {
+                        let index = body.value(*item)?;
+                        if index < 0.0 || index >= vcount as f64 {
+                            return Err(invalid(&format!("vertex index {index} out of range")));
+                        }
+                        // Mesh triangles are 1-based, as in Wavefront files
+                        corners.push(index as u32 + 1);
+                    }
+                    if n < 3 {
+                        return Err(invalid("face with fewer than 3 vertices"));
+                    }
+                    for k in 1..n - 1 {
+                        let t = Triangle(corners[0], corners[k], corners[k + 1]);
+                        mesh.triangles.push(t)
+                    }
+                }
+                other => body.skip(other)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn load_from_reader<R: BufRead>(mut reader: R) -> std::io::Result<Mesh> {
+    let header = read_header(&mut reader)?;
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut body = Body {
+        format: header.format,
+        bytes: &bytes,
+        pos: 0,
+    };
+
+    let mut mesh = Mesh {
+        vertices: Vec::new(),
+        normals: Vec::new(),
+        texcoords: Vec::new(),
+        colors: Vec::new(),
+        triangles: Vec::new(),
+        uv_triangles: Vec::new(),
+        materials: Vec::new(),
+        material_names: Vec::new(),
+        groups: Vec::new(),
+    };
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => read_vertices(&mut body, element, &mut mesh)?,
+            "face" => read_faces(&mut body, element, &mut mesh)?,
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        body.skip(property)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(mesh)
+}
+
+pub fn load(path: &str) -> std::io::Result<Mesh> {
+    load_from_reader(BufReader::new(File::open(path)?))
+}
+
+// Writes the vertices and triangles of `mesh`, with normals, colors and
+// texcoords when there is one per vertex. Colors are stored as `uchar`s.
+pub fn write<W: Write>(out: &mut W, mesh: &Mesh, format: Format) -> std::io::Result<()> {
+    let per_vertex = |n: usize| n > 0 && n == mesh.vertices.len();
+    let normals = per_vertex(mesh.normals.len());
+    let colors = per_vertex(mesh.colors.len());
+    let texcoords = per_vertex(mesh.texcoords.len());
+
+    let format_name = match format {
+        Format::Ascii => "ascii",
+        Format::BinaryLe => "binary_little_endian",
+        Format::BinaryBe => "binary_big_endian",
+    };
+    writeln!(out, "ply\nformat {format_name} 1.0")?;
+    writeln!(out, "element vertex {}", mesh.vertices.len())?;
+    for name in ["x", "y", "z"] {
+        writeln!(out, "property float {name}")?;
+    }
+    if normals {
+        for name in ["nx", "ny", "nz"] {
+            writeln!(out, "property float {name}")?;
+        }
+    }
+    if texcoords {
+        for name in ["u", "v"] {
+            writeln!(out, "property float {name}")?;
+        }
+    }
+    if colors {
+        for name in ["red", "green", "blue"] {
+            writeln!(out, "property uchar {name}")?;
+        }
+    }
+    writeln!(out, "element face {}", mesh.triangles.len())?;
+    writeln!(out, "property list uchar int vertex_indices\nend_header")?;
+
+    let to_byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    let put = |out: &mut W, bytes: &[u8]| -> std::io::Result<()> {
+        match format {
+            Format::BinaryBe => out.write_all(&bytes.iter().rev().copied().collect::<Vec<u8>>()),
+            _ => out.write_all(bytes),
+        }
+    };
+    for (i, V3(x, y, z)) in mesh.vertices.iter().enumerate() {
+        let mut floats = vec![*x, *y, *z];
+        if normals {
+            let V3(nx, ny, nz) = mesh.normals[i];
+            floats.extend([nx, ny, nz]);
+        }
+        if texcoords {
+            let V3(u, v, _) = mesh.texcoords[i];
+            floats.extend([u, v]);
+        }
+        let rgb = if colors {
+            let V3(r, g, b) = mesh.colors[i];
+            vec![to_byte(r), to_byte(g), to_byte(b)]
+        } else {
+            Vec::new()
+        };
+        if format == Format::Ascii {
+            let words: Vec<String> = floats
+                .iter()
+                .map(|f| f.to_string())
+                .chain(rgb.iter().map(|c| c.to_string()))
+                .collect();
+            writeln!(out, "{}", words.join(" "))?;
+        } else {
+            for f in floats {
+                put(out, &f.to_le_bytes())?;
+            }
+            out.write_all(&rgb)?;
+        }
+    }
+    for Triangle(t0, t1, t2) in &mesh.triangles {
+        // PLY indices are 0-based
+        let corners = [*t0 as i32 - 1, *t1 as i32 - 1, *t2 as i32 - 1];
+        if format == Format::Ascii {
+            writeln!(out, "3 {} {} {}", corners[0], corners[1], corners[2])?;
+        } else {
+            out.write_all(&[3])?;
+            for c in corners {
+                put(out, &c.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn save(path: &str, mesh: &Mesh, format: Format) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    write(&mut out, mesh, format)?;
+    out.flush()
+}
+
+pub fn is_ply(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC) && matches!(bytes.get(3), Some(b'\n') | Some(b'\r'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROPERTIES: &str = "element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property uchar flags
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+";
+
+    const ASCII_BODY: &str = "0 0 0 0 0 1 255 0 0
+1 0 0 0 0 1 0 255 0
+1 1 0 0 0 1 0 0 255
+0 1 0 0 0 1 255 255 255
+7 4 0 1 2 3
+0 2
+";
+
+    // Binary encoding of `ASCII_BODY`.
+    fn binary_body(big_endian: bool) -> Vec<u8> {
+        fn put<const N: usize>(out: &mut Vec<u8>, mut bytes: [u8; N], big_endian: bool) {
+            if big_endian {
+                bytes.reverse();
+            }
+            out.extend_from_slice(&bytes);
+        }
+        let mut out = Vec::new();
+        let positions = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0f32]];
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255u8]];
+        for ([x, y], rgb) in positions.iter().zip(colors) {
+            for c in [*x, *y, 0.0, 0.0, 0.0, 1.0] {
+                put(&mut out, c.to_le_bytes(), big_endian);
+            }
+            out.extend_from_slice(&rgb);
+        }
+        out.extend_from_slice(&[7, 4]);
+        for i in 0..4i32 {
+            put(&mut out, i.to_le_bytes(), big_endian);
+        }
+        for i in [0i32, 2] {
+            put(&mut out, i.to_le_bytes(), big_endian);
+        }
+        out
+    }
+
+    fn file(format: &str, body: &[u8]) -> Vec<u8> {
+        let mut out = format!("ply\nformat {format} 1.0\ncomment test\n{PROPERTIES}").into_bytes();
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[test]
+    fn test_formats() {
+        let ascii = load_from_reader(&file("ascii", ASCII_BODY.as_bytes())[..]).unwrap();
+        assert_eq!(ascii.vertices.len(), 4);
+        assert_eq!(ascii.vertices[2], V3(1.0, 1.0, 0.0));
+        assert_eq!(ascii.normals, vec![V3(0.0, 0.0, 1.0); 4]);
+        assert_eq!(ascii.colors[1], V3(0.0, 1.0, 0.0));
+        assert_eq!(ascii.triangles, vec![Triangle(1, 2, 3), Triangle(1, 3, 4)]);
+
+        let le = file("binary_little_endian", &binary_body(false));
+        assert!(is_ply(&le));
+        assert_eq!(load_from_reader(&le[..]).unwrap(), ascii);
+        let be = file("binary_big_endian", &binary_body(true));
+        assert_eq!(load_from_reader(&be[..]).unwrap(), ascii);
+    }
+
+    #[test]
+    fn test_write_roundtrip() {
+        let mesh = load_from_reader(&file("ascii", ASCII_BODY.as_bytes())[..]).unwrap();
+        let mut textured = mesh.clone();
+        textured.texcoords = mesh
+            .vertices
+            .iter()
+            .map(|V3(x, y, _)| V3(*x, *y, 0.0))
+            .collect();
+        for mesh in [mesh, textured] {
+            for format in [Format::Ascii, Format::BinaryLe, Format::BinaryBe] {
+                let mut out = Vec::new();
+                write(&mut out, &mesh, format).unwrap();
+                assert_eq!(load_from_reader(&out[..]).unwrap(), mesh, "{format:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_corpus() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/corpus/quad.ply");
+        let mesh = load(path).unwrap();
+        assert_eq!(mesh.triangles, vec![Triangle(1, 2, 3), Triangle(1, 3, 4)]);
+        assert!(mesh.normals.is_empty() && mesh.colors.is_empty());
+    }
+
+    #[test]
+    fn test_malformed() {
+        let truncated = file("binary_little_endian", &binary_body(false)[..40]);
+        assert!(load_from_reader(&truncated[..]).is_err());
+        let out_of_range = ASCII_BODY.replace("7 4 0 1 2 3", "7 4 0 1 2 4");
+        assert!(load_from_reader(&file("ascii", out_of_range.as_bytes())[..]).is_err());
+        assert!(load_from_reader(&b"ply\nformat ascii 2.0\nend_header\n"[..]).is_err());
+        assert!(load_from_reader(&b"obj\n"[..]).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+use crate::loader::{Mesh, Triangle, V3};
+use std::collections::HashMap;
+
+// STL reader, for both the binary and the ASCII variants.
+//
+// STL stores each triangle with its own three vertices. Without welding the
+// mesh gets three vertices per triangle; `weld` merges vertices with equal
+// positions. Facet normals are not kept: they are often missing or wrong,
+// and the scene computes its own.
+
+const HEADER_SIZE: usize = 80 + 4;
+const FACET_SIZE: usize = 4 * 12 + 2;
+
+fn invalid(what: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("stl: {what}"))
+}
+
+// Binary files may also start with "solid", so they are told apart by their
+// size, which the triangle count determines.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    is_binary_header(bytes, bytes.len() as u64)
+}
+
+// Like `is_binary`, from the start of a file of `size` bytes.
+pub fn is_binary_header(header: &[u8], size: u64) -> bool {
+    if header.len() < HEADER_SIZE {
+        return false;
+    }
+    let count = u32::from_le_bytes([header[80], header[81], header[82], header[83]]) as u64;
+    count * FACET_SIZE as u64 + HEADER_SIZE as u64 == size
+}
+
+pub fn is_stl(bytes: &[u8]) -> bool {
+    is_stl_header(bytes, bytes.len() as u64)
+}
+
+pub fn is_stl_header(header: &[u8], size: u64) -> bool {
+    is_binary_header(header, size) || header.trim_ascii_start().starts_with(b"solid")
+}
+
+fn read_binary(bytes: &[u8], mesh: &mut Mesh) {
+    let f32_at =
+        |i: usize| f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+    for facet in (HEADER_SIZE..bytes.len()).step_by(FACET_SIZE) {
+        // skip the normal
+        for corner in 1..4 {
+            let at = facet + 12 * corner;
+            mesh.vertices
+                .push(V3(f32_at(at), f32_at(at + 4), f32_at(at + 8)));
+        }
+        let n = mesh.vertices.len() as u32;
+        mesh.triangles.push(Triangle(n - 2, n - 1, n));
+    }
+}
+
+fn read_ascii(text: &str, mesh: &mut Mesh) -> std::io::Result<()> {
+    let mut corners = 0;
+    for (i, line) in text.lines().enumerate() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["vertex", x, y, z] => {
+                let coord = |s: &str| {
+                    s.parse::<f32>()
+                        .map_err(|_| invalid(&format!("line {}: bad number {s:?}", i + 1)))
+                };
+                mesh.vertices.push(V3(coord(x)?, coord(y)?, coord(z)?));
+                corners += 1;
+            }
+            ["endloop"] => {
+                if corners != 3 {
+                    return Err(invalid(&format!("line {}: facet is not a triangle", i + 1)));
+                }
+                let n = mesh.vertices.len() as u32;
+                mesh.triangles.push(Triangle(n - 2, n - 1, n));
+                corners = 0;
+            }
+            ["solid", ..]
+            | ["endsolid", ..]
+            | ["facet", ..]
+            | ["outer", "loop"]
+            | ["endfacet"]
+            | [] => (),
+            _ => {
+                return Err(invalid(&format!(
+                    "line {}: unexpected {:?}",
+                    i + 1,
+                    line.trim()
+                )))
+            }
+        }
+    }
+    if corners != 0 {
+        return Err(invalid("unterminated facet"));
+    }
+    Ok(())
+}
+
+// Merges vertices with bitwise equal positions (treating -0.0 as 0.0) and
+// renumbers the triangles accordingly.
+pub fn weld(mesh: &mut Mesh) {
+    let key = |V3(x, y, z): V3| [x + 0.0, y + 0.0, z + 0.0].map(f32::to_bits);
+    let mut index: HashMap<[u32; 3], u32> = HashMap::with_capacity(mesh.vertices.len() / 2);
+    let mut vertices = Vec::new();
+    // new 1-based index of each old vertex
+    let remap: Vec<u32> = mesh
+        .vertices
+        .iter()
+        .map(|v| {
+            *index.entry(key(*v)).or_insert_with(|| {
+                vertices.push(*v);
+                vertices.len() as u32
+            })
+        })
+        .collect();
+    for Triangle(t0, t1, t2) in mesh.triangles.iter_mut() {
+        *t0 = remap[*t0 as usize - 1];
+        *t1 = remap[*t1 as usize - 1];
+        *t2 = remap[*t2 as usize - 1];
+    }
+    mesh.vertices = vertices;
+}
+
+pub fn load_from_bytes(bytes: &[u8], weld_vertices: bool) -> std::io::Result<Mesh> {
+    let mut mesh = Mesh {
+        vertices: Vec::new(),
+        normals: Vec::new(),
+        texcoords: Vec::new(),
+        colors: Vec::new(),
+        triangles: Vec::new(),
+        uv_triangles: Vec::new(),
+        materials: Vec::new(),
+        material_names: Vec::new(),
+        groups: Vec::new(),
+    };
+    if is_binary(bytes) {
+        read_binary(bytes, &mut mesh);
+    } else {
+        let text = std::str::from_utf8(bytes).map_err(|_| invalid("not a binary or ASCII file"))?;
+        if !text.trim_start().starts_with("solid") {
+            return Err(invalid("bad magic"));
+        }
+        read_ascii(text, &mut mesh)?;
+    }
+    if weld_vertices {
+        weld(&mut mesh);
+    }
+    Ok(mesh)
+}
+
+pub fn load(path: &str, weld_vertices: bool) -> std::io::Result<Mesh> {
+    load_from_bytes(&std::fs::read(path)?, weld_vertices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A unit quad, as two facets.
+    const ASCII: &str = "solid quad
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 -0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid quad
+";
+
+    fn binary() -> Vec<u8> {
+        // a header starting with "solid", as some exporters write
+        let mut out = b"solid binary".to_vec();
+        out.resize(80, b' ');
+        out.extend_from_slice(&2u32.to_le_bytes());
+        let facets = [
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]],
+            [[0.0, 0.0, -0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0f32]],
+        ];
+        for facet in facets {
+            for c in [0.0, 0.0, 1.0f32] {
+                out.extend_from_slice(&c.to_le_bytes());
+            }
+            for c in facet.iter().flatten() {
+                out.extend_from_slice(&c.to_le_bytes());
+            }
+            out.extend_from_slice(&[0, 0]);
+        }
+        out
+    }
+
+    #[test]
+    fn test_ascii_and_binary() {
+        let bytes = binary();
+        assert!(is_binary(&bytes) && is_stl(&bytes));
+        assert!(!is_binary(ASCII.as_bytes()) && is_stl(ASCII.as_bytes()));
+        assert!(is_binary_header(&bytes[..HEADER_SIZE], bytes.len() as u64));
+        assert!(!is_binary_header(
+            &bytes[..HEADER_SIZE],
+            bytes.len() as u64 - 1
+        ));
+
+        let ascii = load_from_bytes(ASCII.as_bytes(), false).unwrap();
+        assert_eq!(ascii.vertices.len(), 6);
+        assert_eq!(ascii.triangles, vec![Triangle(1, 2, 3), Triangle(4, 5, 6)]);
+        assert_eq!(load_from_bytes(&bytes, false).unwrap(), ascii);
+    }
+
+    #[test]
+    fn test_weld() {
+        let mesh = load_from_bytes(&binary(), true).unwrap();
+        assert_eq!(
+            mesh.vertices,
+            vec![
+                V3(0.0, 0.0, 0.0),
+                V3(1.0, 0.0, 0.0),
+                V3(1.0, 1.0, 0.0),
+                V3(0.0, 1.0, 0.0)
+            ]
+        );
+        assert_eq!(mesh.triangles, vec![Triangle(1, 2, 3), Triangle(1, 3, 4)]);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let bytes = binary();
+        assert!(load_from_bytes(&bytes[..bytes.len() - 1], false).is_err());
+        let quad = ASCII.replace("      vertex 0 1 0\n", "");
+        assert!(load_from_bytes(quad.as_bytes(), false).is_err());
+        assert!(load_from_bytes(b"v 0 0 0", false).is_err());
+    }
+}